@@ -1,9 +1,10 @@
 use nes::{
     apu, io, ppu,
-    sprites::{self, SpriteState},
+    sprites::{self, Metasprite, SpritePart, SpriteState},
+    text_box::{draw_frame, BoxTiles},
 };
 
-use crate::draw::draw_box;
+use crate::sfx;
 
 // statically allocated memory
 static mut STATE: Option<Game> = None;
@@ -22,7 +23,7 @@ pub unsafe fn init() {
     // palettes and border
     ppu::write_bytes(ppu::PAL_BG_0, &[0x0D, 0x36, 0x16, 0x23]);
     ppu::write_bytes(ppu::PAL_SPRITE_0 + 3, &[0x30]);
-    draw_box(1, 1, 30, 28);
+    draw_frame(&BORDER, 0x2000, 1, 1, 30, 28);
 
     // bricks
     let mut addr = 0x2082;
@@ -47,14 +48,12 @@ pub fn frame(apu: &mut apu::APU, sprites: &mut SpriteState) {
         0x80,
         0,
     );
-    for i in 0..game.paddle.width {
-        sprites.add(
-            TOP_MARGIN + game.paddle.x + (i * 8),
-            LEFT_MARGIN + game.paddle.y - 1,
-            0x87,
-            0,
-        );
-    }
+    sprites.add_metasprite(
+        (TOP_MARGIN + game.paddle.x) as i16,
+        (LEFT_MARGIN + game.paddle.y - 1) as i16,
+        &PADDLE,
+        0,
+    );
 }
 
 pub unsafe fn render() {
@@ -89,6 +88,26 @@ fn get_rng() -> u8 {
     unsafe { (SEED >> 8) as u8 }
 }
 
+const BORDER: BoxTiles = BoxTiles {
+    top_left: 0x73,
+    top_right: 0x74,
+    bottom_left: 0x75,
+    bottom_right: 0x76,
+    vertical: 0x77,
+    horizontal: 0x78,
+    fill: 0,
+};
+
+const PADDLE: Metasprite = Metasprite::new(&[
+    SpritePart::new(0, 0, 0x87, 0),
+    SpritePart::new(8, 0, 0x87, 0),
+    SpritePart::new(16, 0, 0x87, 0),
+    SpritePart::new(24, 0, 0x87, 0),
+    SpritePart::new(32, 0, 0x87, 0),
+    SpritePart::new(40, 0, 0x87, 0),
+    SpritePart::new(48, 0, 0x87, 0),
+]);
+
 const WIDTH: u8 = 224;
 const HEIGHT: u8 = 208;
 const BRICKS_WIDE: usize = 14;
@@ -167,7 +186,7 @@ impl Game {
             paddle: Paddle {
                 x: WIDTH / 2,
                 y: HEIGHT - 10,
-                width: 7,
+                width: PADDLE.parts.len() as u8,
             },
             bricks: [Brick::Empty; 140],
             destroyed: [None; 4],
@@ -191,7 +210,8 @@ impl Game {
 
         if self.ball.dy == 0 {
             // dead
-            if buttons & io::START != 0 {
+            // only on a fresh press, or holding START keeps restarting
+            if io::just_pressed(io::Button::Start) {
                 unsafe {
                     ppu::disable_nmi();
                     init();
@@ -256,7 +276,7 @@ impl Game {
                     // rollback if collide
                     self.ball.x = old_x;
                     self.ball.y = old_y;
-                    apu.play_sfx(apu::Sfx::MenuBoop);
+                    apu.play_sfx(&sfx::BRICK);
                     break;
                 }
             }
@@ -265,11 +285,11 @@ impl Game {
         // Screen collision
         if self.ball.x == 0 || self.ball.x + BALL_DIAMETER >= WIDTH {
             self.ball.dx = -self.ball.dx;
-            apu.play_sfx(apu::Sfx::Lock);
+            apu.play_sfx(&sfx::BOUNCE);
         }
         if self.ball.y == 0 {
             self.ball.dy = -self.ball.dy;
-            apu.play_sfx(apu::Sfx::Lock);
+            apu.play_sfx(&sfx::BOUNCE);
         }
         // paddle collision
         if self.ball.y + BALL_DIAMETER >= self.paddle.y {
@@ -277,11 +297,11 @@ impl Game {
                 && self.ball.x + BALL_RADIUS < self.paddle.x + (self.paddle.width * 8)
             {
                 self.ball.dy = -self.ball.dy;
-                apu.play_sfx(apu::Sfx::Lock);
+                apu.play_sfx(&sfx::BOUNCE);
             } else {
                 self.ball.dx = 0;
                 self.ball.dy = 0;
-                apu.play_sfx(apu::Sfx::Topout);
+                apu.play_sfx(&sfx::MISS);
             }
         }
     }
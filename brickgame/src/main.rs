@@ -4,8 +4,8 @@
 
 use nes::{apu, io, ppu, sprites};
 
-mod draw;
 mod game;
+mod sfx;
 
 // fixed memory usage;
 // 0x80 - nmi check bit
@@ -27,6 +27,7 @@ fn _main(_argc: isize, _argv: *const *const u8) -> isize {
         apu.run_sfx();
         //apu::run_sfx();
         game::frame(&mut apu, &mut sprite_state);
+        sprite_state.finish();
     }
 }
 
@@ -0,0 +1,26 @@
+use nes::{
+    apu::{Channel, SfxStep, SoundEffect},
+    note::Note,
+};
+
+pub const BRICK: SoundEffect =
+    SoundEffect::new(Channel::Pulse1, &[SfxStep::new(3, 0x090, 0b10110111)]);
+
+pub const BOUNCE: SoundEffect = SoundEffect::new(
+    Channel::Pulse1,
+    &[
+        SfxStep::note(3, Note::parse("D#2"), 0b10110110),
+        SfxStep::note(1, Note::parse("C2"), 0b10110110),
+    ],
+);
+
+// low buzz, over anything else still playing
+pub const MISS: SoundEffect = SoundEffect::new(
+    Channel::Pulse1,
+    &[
+        SfxStep::note(8, Note::parse("C2"), 0b00111111),
+        SfxStep::note(8, Note::parse("B1"), 0b00111100),
+        SfxStep::note(12, Note::parse("A#1"), 0b00111001),
+    ],
+)
+.with_priority(1);
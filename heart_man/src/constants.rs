@@ -1,4 +1,4 @@
-use nes::constants::ROW;
+use nes::{charset, constants::ROW};
 
 pub const N_ROWS: u8 = 28; // 28 fills screen
 pub const GRID_SIZE: u16 = (ROW as u16) * (N_ROWS as u16);
@@ -15,5 +15,6 @@ pub const HEART_SPRITE: u8 = 0x63;
 pub const WALL_SPRITE: u8 = 0x60;
 pub const COIN_SPRITE: u8 = HEART_SPRITE + 7;
 pub const AT_SPRITE: u8 = HEART_SPRITE - 4;
+pub const DEAD_SPRITE: u8 = charset::ASCII.tile('x');
 
 pub const DT: u8 = 1;
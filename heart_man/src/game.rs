@@ -2,22 +2,25 @@ use core::{mem::transmute, ptr::addr_of_mut};
 
 use nes::{
     addr::Addr,
-    apu::{self, Sfx},
+    animation::{Animation, AnimationState, Frame, Mode},
+    apu,
     capped_vec::CappedVec,
-    io, ppu,
+    charset, io, ppu,
     ppu_buffer::{self, BufferTrait},
     sprites::SpriteState,
+    text,
     utils::Sign,
     vec2::{DPos, Orientation, Pos, Vec2},
 };
 
 use crate::{
     constants::{
-        AT_SPRITE, DT, GRID_SIZE, HEART_SPRITE, HEIGHT, ORIGIN, PLAYER_SPEED, PLAYER_WIDTH,
+        AT_SPRITE, DEAD_SPRITE, DT, GRID_SIZE, HEART_SPRITE, HEIGHT, ORIGIN, PLAYER_SPEED, PLAYER_WIDTH,
         WALL_SPRITE, WIDTH,
     },
     level::{draw_level, get_tile_at, make_level, map_pos_to_tile_index, Tile},
     rng::{get_seeds, Rng},
+    sfx,
     utils::{u16_to_decimal, u8_to_decimal},
     Buffer,
 };
@@ -36,7 +39,8 @@ pub unsafe fn init() {
     draw_level(&LEVEL_TILES);
 
     // text
-    ppu::draw_ascii(ORIGIN + 0x06, "HEART-MAN");
+    ppu::write_addr(ORIGIN + 0x06);
+    ppu::draw_tiles(text!("HEART-MAN"));
 }
 
 pub fn frame(game: &mut Game, apu: &mut apu::APU, sprites: &mut SpriteState) {
@@ -45,9 +49,23 @@ pub fn frame(game: &mut Game, apu: &mut apu::APU, sprites: &mut SpriteState) {
     game.draw(sprites);
 }
 
+static ALIVE: Animation = Animation::new(&[Frame::tile(HEART_SPRITE, 1)], Mode::Loop);
+// blink between heart and 'x' a few times, then stay dead
+static DYING: Animation = Animation::new(
+    &[
+        Frame::tile(DEAD_SPRITE, 8),
+        Frame::tile(HEART_SPRITE, 8),
+        Frame::tile(DEAD_SPRITE, 8),
+        Frame::tile(HEART_SPRITE, 8),
+        Frame::tile(DEAD_SPRITE, 1),
+    ],
+    Mode::OneShot,
+);
+
 struct Player {
     pos: Pos,
     dead: bool,
+    anim: AnimationState,
 }
 
 struct Meanie {
@@ -89,6 +107,7 @@ impl Game {
                     y: 8 + 8,
                 },
                 dead: false,
+                anim: AnimationState::new(&ALIVE),
             },
             grabbed_coins: CappedVec::new(),
             grabbed_coin_index: None,
@@ -118,7 +137,7 @@ impl Game {
                 self.grabbed_coins.try_push(index).expect("Pockets full!");
                 self.grabbed_coin_index = Some(index);
                 self.n_coins -= 1;
-                apu.play_sfx(Sfx::LevelUp);
+                apu.play_sfx(&sfx::COIN);
             }
         }
 
@@ -127,6 +146,7 @@ impl Game {
             if !self.player.dead && (self.player.pos.l1_dist(&meanie.pos) < PLAYER_WIDTH) {
                 on_player_death(apu);
                 self.player.dead = true;
+                self.player.anim.play(&DYING);
             }
         }
     }
@@ -147,13 +167,11 @@ impl Game {
         // draw_digits(Addr(ORIGIN).offset(6), self.player.pos.x);
         //draw_digits(Addr(ORIGIN).offset(6 + 4), self.player.pos.y);
 
-        sprites.add_at_pos(
-            &self.player.pos,
-            if self.player.dead {
-                'x' as u8 - 32
-            } else {
-                HEART_SPRITE
-            },
+        self.player.anim.update();
+        self.player.anim.draw(
+            sprites,
+            self.player.pos.x as i16,
+            self.player.pos.y as i16,
             0,
         );
 
@@ -221,7 +239,7 @@ fn draw_digits(addr: Addr, x: u8) {
     for (x, y) in digits.iter_mut().rev().zip(u8_to_decimal(x).into_iter()) {
         *x = y
     }
-    Buffer::tiles(addr, digits.map(|d| io::digit_to_ascii(d) - 32).into_iter())
+    Buffer::tiles(addr, digits.map(|d| charset::ASCII.digit(d)).into_iter())
 }
 
 fn draw_digits_16(addr: Addr, x: u16) {
@@ -229,12 +247,12 @@ fn draw_digits_16(addr: Addr, x: u16) {
     for (x, y) in digits.iter_mut().rev().zip(u16_to_decimal(x).into_iter()) {
         *x = y
     }
-    Buffer::tiles(addr, digits.map(|d| io::digit_to_ascii(d) - 32).into_iter())
+    Buffer::tiles(addr, digits.map(|d| charset::ASCII.digit(d)).into_iter())
 }
 
 fn on_player_death(apu: &mut apu::APU) {
-    apu.play_sfx(Sfx::Topout);
-    Buffer::draw_text(Addr(ORIGIN + 15), " IS DEAD");
+    apu.play_sfx(&sfx::DEATH);
+    Buffer::draw_tiles(Addr(ORIGIN + 15), text!(" IS DEAD"));
 }
 fn update_player(player: &mut Player, tiles: &[Tile]) {
     if player.dead {
@@ -0,0 +1,28 @@
+use nes::{
+    apu::{Channel, SfxStep, SoundEffect},
+    note::Note,
+};
+
+// two-note chime, ringing out
+pub const COIN: SoundEffect = SoundEffect::new(
+    Channel::Pulse1,
+    &[
+        SfxStep::note(3, Note::parse("B4"), 0b01111100),
+        SfxStep::note(8, Note::parse("E5"), 0b01111111),
+        SfxStep::note(6, Note::parse("E5"), 0b01110110),
+        SfxStep::note(6, Note::parse("E5"), 0b01110010),
+    ],
+);
+
+// falling and fading, over anything else still playing
+pub const DEATH: SoundEffect = SoundEffect::new(
+    Channel::Pulse1,
+    &[
+        SfxStep::note(5, Note::parse("C4"), 0b10111111),
+        SfxStep::note(5, Note::parse("A3"), 0b10111101),
+        SfxStep::note(5, Note::parse("F#3"), 0b10111011),
+        SfxStep::note(5, Note::parse("D#3"), 0b10111001),
+        SfxStep::note(10, Note::parse("C3"), 0b10110111),
+    ],
+)
+.with_priority(1);
@@ -1,4 +1,10 @@
 fn main() {
+    println!("cargo:rustc-check-cfg=cfg(target_arch, values(\"mos\"))");
+
+    // the NMI glue is 6502 asm; host builds use the mock bus instead
+    if std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() != Ok("mos") {
+        return;
+    }
     cc::Build::new()
         .compiler("clang")
         .target("mos-nes")
@@ -1,16 +1,19 @@
 use core::ops::{Deref, DerefMut};
 
+use crate::bus;
+
 #[derive(Copy, Clone)]
 pub struct Addr(pub u16);
+#[allow(clippy::missing_safety_doc)]
 impl Addr {
     pub fn as_ptr(self) -> *mut u8 {
-        self.0 as *mut u8
+        bus::ptr(self.0)
     }
     pub unsafe fn read(self) -> u8 {
-        self.as_ptr().read_volatile()
+        bus::read(self.0)
     }
     pub unsafe fn write(self, value: u8) {
-        self.as_ptr().write_volatile(value)
+        bus::write(self.0, value)
     }
     pub fn offset(self, count: isize) -> Self {
         Addr(self.0 + (count as u16))
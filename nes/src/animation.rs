@@ -0,0 +1,246 @@
+// Frame-by-frame sprite animations kept in ROM, plus a small per-entity
+// state that steps through them once per frame:
+//     static WALK: Animation = Animation::new(
+//         &[Frame::tile(0x10, 8), Frame::tile(0x11, 8)],
+//         Mode::Loop,
+//     );
+//     let mut anim = AnimationState::new(&WALK);
+//     // each frame
+//     anim.update();
+//     anim.draw(&mut sprites, x, y, 0);
+//
+// Declare animations `static`, not `const`: `AnimationState::play` tells
+// them apart by address, and every use of a const can be a new copy.
+use crate::sprites::{Metasprite, SpriteState};
+
+#[derive(Copy, Clone)]
+pub enum Image {
+    Tile(u8),
+    Metasprite(&'static Metasprite),
+}
+
+#[derive(Copy, Clone)]
+pub struct Frame {
+    pub image: Image,
+    // in frames; 0 is treated as 1
+    pub duration: u8,
+}
+impl Frame {
+    pub const fn tile(tile: u8, duration: u8) -> Self {
+        Self {
+            image: Image::Tile(tile),
+            duration,
+        }
+    }
+    pub const fn metasprite(sprite: &'static Metasprite, duration: u8) -> Self {
+        Self {
+            image: Image::Metasprite(sprite),
+            duration,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Mode {
+    Loop,
+    // stay on the last frame once it's done
+    OneShot,
+    // forwards then backwards, without showing the end frames twice
+    PingPong,
+}
+
+pub struct Animation {
+    pub frames: &'static [Frame],
+    pub mode: Mode,
+}
+impl Animation {
+    // `frames` must not be empty, and at most 256 since frames are counted
+    // in a u8
+    pub const fn new(frames: &'static [Frame], mode: Mode) -> Self {
+        if frames.is_empty() {
+            panic!("animation: no frames");
+        }
+        if frames.len() > 256 {
+            panic!("animation: more than 256 frames");
+        }
+        Self { frames, mode }
+    }
+}
+
+pub struct AnimationState {
+    animation: &'static Animation,
+    frame: u8,
+    timer: u8,
+    backwards: bool,
+    done: bool,
+}
+impl AnimationState {
+    pub const fn new(animation: &'static Animation) -> Self {
+        Self {
+            animation,
+            frame: 0,
+            timer: 0,
+            backwards: false,
+            done: false,
+        }
+    }
+
+    // switch to `animation`, carrying on if it's already playing (the same
+    // static, see above)
+    pub fn play(&mut self, animation: &'static Animation) {
+        if !core::ptr::eq(self.animation, animation) {
+            *self = Self::new(animation);
+        }
+    }
+
+    pub fn restart(&mut self) {
+        *self = Self::new(self.animation);
+    }
+
+    // a one-shot animation has shown its last frame for its full duration
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub fn frame(&self) -> &'static Frame {
+        &self.animation.frames[self.frame as usize]
+    }
+
+    // advance by one frame
+    pub fn update(&mut self) {
+        if self.done {
+            return;
+        }
+        self.timer += 1;
+        if self.timer < self.frame().duration {
+            return;
+        }
+        self.timer = 0;
+
+        let last = self.animation.frames.len() as u8 - 1;
+        match self.animation.mode {
+            Mode::Loop => {
+                self.frame = if self.frame >= last {
+                    0
+                } else {
+                    self.frame + 1
+                }
+            }
+            Mode::OneShot => {
+                if self.frame >= last {
+                    self.done = true;
+                } else {
+                    self.frame += 1;
+                }
+            }
+            Mode::PingPong => {
+                if last == 0 {
+                    return;
+                }
+                if self.backwards && self.frame == 0 || !self.backwards && self.frame >= last {
+                    self.backwards = !self.backwards;
+                }
+                if self.backwards {
+                    self.frame -= 1;
+                } else {
+                    self.frame += 1;
+                }
+            }
+        }
+    }
+
+    // same arguments as `SpriteState::add_metasprite`
+    pub fn draw(&self, sprites: &mut SpriteState, x: i16, y: i16, attr: u8) {
+        match self.frame().image {
+            Image::Tile(tile) => {
+                if (0..=255).contains(&x) && (0..240).contains(&y) {
+                    sprites.add(x as u8, y as u8, tile, attr);
+                }
+            }
+            Image::Metasprite(sprite) => sprites.add_metasprite(x, y, sprite, attr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static BLINK: Animation = Animation::new(&[Frame::tile(1, 2), Frame::tile(2, 2)], Mode::Loop);
+    static STILL: Animation = Animation::new(&[Frame::tile(3, 1)], Mode::Loop);
+    static THREE: [Frame; 3] = [Frame::tile(1, 1), Frame::tile(2, 1), Frame::tile(3, 1)];
+
+    // the tiles shown over `count` updates, starting with the current one
+    fn play_for(animation: &'static Animation, count: usize) -> ([u8; 8], AnimationState) {
+        let mut state = AnimationState::new(animation);
+        let mut tiles = [0; 8];
+        for shown in &mut tiles[..count] {
+            *shown = tile(&state);
+            state.update();
+        }
+        (tiles, state)
+    }
+
+    fn tile(state: &AnimationState) -> u8 {
+        match state.frame().image {
+            Image::Tile(tile) => tile,
+            Image::Metasprite(_) => panic!("expected a tile"),
+        }
+    }
+
+    #[test]
+    fn loop_wraps() {
+        static LOOP: Animation = Animation::new(&THREE, Mode::Loop);
+        let (tiles, state) = play_for(&LOOP, 8);
+        assert_eq!(tiles, [1, 2, 3, 1, 2, 3, 1, 2]);
+        assert!(!state.is_done());
+    }
+
+    #[test]
+    fn one_shot_stops_on_the_last_frame() {
+        static ONCE: Animation = Animation::new(&THREE, Mode::OneShot);
+        let (tiles, mut state) = play_for(&ONCE, 6);
+        assert_eq!(tiles[..6], [1, 2, 3, 3, 3, 3]);
+        assert!(state.is_done());
+        state.restart();
+        assert!(!state.is_done());
+        assert_eq!(tile(&state), 1);
+    }
+
+    #[test]
+    fn one_shot_is_done_after_the_last_frame_has_played() {
+        static SLOW: Animation =
+            Animation::new(&[Frame::tile(1, 1), Frame::tile(2, 2)], Mode::OneShot);
+        let mut state = AnimationState::new(&SLOW);
+        state.update();
+        state.update();
+        assert!(!state.is_done());
+        state.update();
+        assert!(state.is_done());
+        assert_eq!(tile(&state), 2);
+    }
+
+    #[test]
+    fn ping_pong_shows_the_ends_once() {
+        static BOUNCE: Animation = Animation::new(&THREE, Mode::PingPong);
+        let (tiles, _) = play_for(&BOUNCE, 8);
+        assert_eq!(tiles, [1, 2, 3, 2, 1, 2, 3, 2]);
+        static ONE: Animation = Animation::new(&[Frame::tile(3, 1)], Mode::PingPong);
+        let (tiles, _) = play_for(&ONE, 3);
+        assert_eq!(tiles[..3], [3, 3, 3]);
+    }
+
+    #[test]
+    fn play_carries_on_with_the_same_animation() {
+        let mut state = AnimationState::new(&BLINK);
+        state.update();
+        state.update();
+        assert_eq!(tile(&state), 2);
+        state.play(&BLINK);
+        assert_eq!(tile(&state), 2);
+        state.play(&STILL);
+        assert_eq!(tile(&state), 3);
+        state.play(&BLINK);
+        assert_eq!(tile(&state), 1);
+    }
+}
@@ -1,36 +1,380 @@
 // https://www.nesdev.org/wiki/APU_basics
-use crate::addr::Addr;
+//
+// Each channel has a typed handle (`PULSE_1`, `PULSE_2`, `TRIANGLE`,
+// `NOISE`, `DMC`) for its registers, and `Channel` names them for $4015,
+// which turns channels on and off and reports which are still sounding.
+use crate::{
+    addr::Addr,
+    bus::shadow,
+    note::{self, Note},
+    ppu_regs::flags,
+};
 const APU: Addr = Addr(0x4000);
-const PULSE1: Addr = Addr(0x4000);
-#[allow(dead_code)]
-const PULSE2: Addr = Addr(0x4004);
+const STATUS: Addr = Addr(0x4015);
 
+#[derive(Copy, Clone, PartialEq)]
 pub enum Channel {
     Pulse1,
     Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
 }
+#[allow(clippy::missing_safety_doc)]
 impl Channel {
     fn addr(self) -> Addr {
         match self {
-            Channel::Pulse1 => PULSE1,
-            Channel::Pulse2 => PULSE2,
+            Channel::Pulse1 => Addr(0x4000),
+            Channel::Pulse2 => Addr(0x4004),
+            Channel::Triangle => Addr(0x4008),
+            Channel::Noise => Addr(0x400C),
+            Channel::Dmc => Addr(0x4010),
+        }
+    }
+
+    // this channel's bit in $4015
+    pub const fn status(self) -> Status {
+        match self {
+            Channel::Pulse1 => Status::PULSE_1,
+            Channel::Pulse2 => Status::PULSE_2,
+            Channel::Triangle => Status::TRIANGLE,
+            Channel::Noise => Status::NOISE,
+            Channel::Dmc => Status::DMC,
+        }
+    }
+
+    // Disabling a channel silences it and clears its length counter; it
+    // can't make sound again until re-enabled. Enabling the DMC starts its
+    // sample if it isn't already playing.
+    pub unsafe fn enable(self, on: bool) {
+        if self == Channel::Dmc {
+            STATUS.write(ENABLED.get().set(Status::DMC, on).bits());
+            return;
+        }
+        ENABLED.set(ENABLED.get().set(self.status(), on));
+        // Writing the DMC bit set restarts a finished sample, and writing it
+        // clear cuts off one that's playing, so pass on what it's doing.
+        let dmc = status().contains(Status::DMC);
+        STATUS.write(ENABLED.get().set(Status::DMC, dmc).bits());
+    }
+
+    // length counter above zero, or for the DMC, sample bytes left
+    pub fn is_active(self) -> bool {
+        status().contains(self.status())
+    }
+
+    // $4000 bit 5 on pulse and noise, where it also loops the envelope;
+    // bit 7 on the triangle, where it also holds the linear counter
+    fn halt_bit(self) -> u8 {
+        match self {
+            Channel::Triangle => 0x80,
+            Channel::Dmc => 0,
+            _ => 0x20,
+        }
+    }
+
+    // Stop the length counter running down (see `LENGTHS`), or with `false`
+    // let the next triggered note end by itself. Kept across volume and
+    // envelope changes. The DMC has no length counter.
+    pub unsafe fn set_halt(self, halt: bool) {
+        if self == Channel::Dmc {
+            return;
+        }
+        let control = CONTROL.get()[index(self.addr())];
+        let bit = self.halt_bit();
+        write_control(
+            self.addr(),
+            if halt { control | bit } else { control & !bit },
+        );
+    }
+
+    pub fn is_halted(self) -> bool {
+        self != Channel::Dmc && CONTROL.get()[index(self.addr())] & self.halt_bit() != 0
+    }
+}
+
+flags!(Status);
+
+// $4015: enable bits on write, channel status on read
+impl Status {
+    pub const PULSE_1: Status = Status(0b1);
+    pub const PULSE_2: Status = Status(0b10);
+    pub const TRIANGLE: Status = Status(0b100);
+    pub const NOISE: Status = Status(0b1000);
+    pub const DMC: Status = Status(0b10000);
+    // read only
+    pub const FRAME_IRQ: Status = Status(0b1000000);
+    pub const DMC_IRQ: Status = Status(0b10000000);
+}
+
+// pulse, triangle and noise enable bits; the DMC's is only written when
+// it's the channel being switched, see `Channel::enable`
+shadow!(static ENABLED: Status = Status(0));
+
+// also acknowledges the frame interrupt
+pub fn status() -> Status {
+    unsafe { Status(STATUS.read()) }
+}
+
+// last high period byte written to pulse 1, pulse 2 and triangle
+shadow!(static PERIOD_HI: [u8; 3] = [0xFF; 3]);
+// and the length bits of their last trigger, which share its register
+shadow!(static LENGTH_BITS: [u8; 3] = [0; 3]);
+
+// last value written to $4000, $4004, $4008 and $400C, which hold each
+// channel's halt flag next to its volume or linear counter
+shadow!(static CONTROL: [u8; 4] = [0x30, 0x30, 0x80, 0x30]);
+
+// pulse 1, pulse 2, triangle, noise
+fn index(base: Addr) -> usize {
+    (base.addr() as usize - 0x4000) >> 2
+}
+
+unsafe fn write_control(base: Addr, value: u8) {
+    let mut control = CONTROL.get();
+    control[index(base)] = value;
+    CONTROL.set(control);
+    base.write(value);
+}
+
+// Length counter lengths in half frames (120 per second on NTSC) for each
+// `length` (0-31) passed to the `trigger` methods. Triggering a note loads
+// its channel's counter, and while the channel's halt flag is clear the
+// counter runs down and silences the channel at zero. With halt set, the
+// default after `init`, it holds and notes play until silenced.
+// https://www.nesdev.org/wiki/APU_Length_Counter
+pub const LENGTHS: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+fn length_bits(length: u8) -> u8 {
+    (length & 0x1F) << 3
+}
+
+// Write an 11-bit period to the pulse or triangle channel at `base`.
+// Writing the high byte restarts the waveform and reloads the length
+// counter, which clicks, so it's skipped when it hasn't changed unless
+// `trigger` gives a length to load. When it does change, the counter is
+// reloaded with the last trigger's length rather than length 0.
+unsafe fn write_period(base: Addr, period: u16, trigger: Option<u8>) {
+    let hi = (period >> 8) as u8 & 0x07;
+    let i = index(base);
+    let mut shadow = PERIOD_HI.get();
+    let mut lengths = LENGTH_BITS.get();
+    base.offset(2).write(period as u8);
+    if let Some(length) = trigger {
+        lengths[i] = length_bits(length);
+        LENGTH_BITS.set(lengths);
+    } else if shadow[i] == hi {
+        return;
+    }
+    base.offset(3).write(lengths[i] | hi);
+    shadow[i] = hi;
+    PERIOD_HI.set(shadow);
+}
+
+pub struct Pulse {
+    base: Addr,
+}
+pub const PULSE_1: Pulse = Pulse { base: Addr(0x4000) };
+pub const PULSE_2: Pulse = Pulse { base: Addr(0x4004) };
+
+#[allow(clippy::missing_safety_doc)]
+impl Pulse {
+    // duty 0-3 (12.5%, 25%, 50%, 75%), constant volume 0-15
+    pub unsafe fn set_volume(&self, duty: u8, volume: u8) {
+        let halt = CONTROL.get()[index(self.base)] & 0x20;
+        write_control(self.base, (duty & 3) << 6 | halt | 0x10 | (volume & 0x0F));
+    }
+    // the hardware envelope: volume decays from 15 every `period + 1`
+    // quarter frames, restarting from 15 if `looping`, which is the same
+    // bit as the halt flag
+    pub unsafe fn set_envelope(&self, duty: u8, period: u8, looping: bool) {
+        write_control(
+            self.base,
+            (duty & 3) << 6 | (looping as u8) << 5 | (period & 0x0F),
+        );
+    }
+    // $4001/$4005 as is, see https://www.nesdev.org/wiki/APU_Sweep
+    pub unsafe fn set_sweep(&self, sweep: u8) {
+        self.base.offset(1).write(sweep);
+    }
+    // change pitch without restarting the waveform
+    pub unsafe fn set_period(&self, period: u16) {
+        write_period(self.base, period, None);
+    }
+    // start a note: restarts the waveform and envelope and loads the
+    // length counter with `LENGTHS[length]`
+    pub unsafe fn trigger(&self, period: u16, length: u8) {
+        write_period(self.base, period, Some(length));
+    }
+    pub unsafe fn silence(&self) {
+        self.set_volume(0, 0);
+    }
+}
+
+pub struct Triangle;
+pub const TRIANGLE: Triangle = Triangle;
+const TRIANGLE_BASE: Addr = Addr(0x4008);
+
+#[allow(clippy::missing_safety_doc)]
+impl Triangle {
+    // The linear counter is the triangle's only volume control: it plays
+    // for `reload` quarter frames (0-127) after each trigger, or for as
+    // long as the length counter allows with `halt` set.
+    pub unsafe fn set_linear(&self, halt: bool, reload: u8) {
+        write_control(TRIANGLE_BASE, (halt as u8) << 7 | (reload & 0x7F));
+    }
+    // play until told otherwise; sets the halt flag
+    pub unsafe fn on(&self) {
+        self.set_linear(true, 0x7F);
+    }
+    // Stopping the waveform mid-cycle pops slightly, but muting it is the
+    // only option.
+    pub unsafe fn silence(&self) {
+        self.set_linear(true, 0);
+    }
+    pub unsafe fn set_period(&self, period: u16) {
+        write_period(TRIANGLE_BASE, period, None);
+    }
+    // reloads the linear counter and loads the length counter with
+    // `LENGTHS[length]`
+    pub unsafe fn trigger(&self, period: u16, length: u8) {
+        write_period(TRIANGLE_BASE, period, Some(length));
+    }
+}
+
+pub struct Noise;
+pub const NOISE: Noise = Noise;
+const NOISE_BASE: Addr = Addr(0x400C);
+
+#[allow(clippy::missing_safety_doc)]
+impl Noise {
+    // constant volume 0-15
+    pub unsafe fn set_volume(&self, volume: u8) {
+        let halt = CONTROL.get()[index(NOISE_BASE)] & 0x20;
+        write_control(NOISE_BASE, halt | 0x10 | (volume & 0x0F));
+    }
+    // same as `Pulse::set_envelope`, without the duty
+    pub unsafe fn set_envelope(&self, period: u8, looping: bool) {
+        write_control(NOISE_BASE, (looping as u8) << 5 | (period & 0x0F));
+    }
+    // `period` 0-15, 0 being the highest pitch; `short` gives the metallic
+    // 93-step sequence instead of white noise
+    pub unsafe fn set_period(&self, short: bool, period: u8) {
+        NOISE_BASE
+            .offset(2)
+            .write((short as u8) << 7 | (period & 0x0F));
+    }
+    // restarts the envelope and loads the length counter with
+    // `LENGTHS[length]`
+    pub unsafe fn trigger(&self, length: u8) {
+        NOISE_BASE.offset(3).write(length_bits(length));
+    }
+    pub unsafe fn silence(&self) {
+        self.set_volume(0);
+    }
+}
+
+pub struct Dmc;
+pub const DMC: Dmc = Dmc;
+const DMC_BASE: Addr = Addr(0x4010);
+
+#[allow(clippy::missing_safety_doc)]
+impl Dmc {
+    // `rate` 0-15 picks the sample rate (4.2 kHz to 33.1 kHz on NTSC);
+    // `irq` raises an interrupt when a non-looping sample ends
+    pub unsafe fn set_rate(&self, rate: u8, looping: bool, irq: bool) {
+        DMC_BASE.write((irq as u8) << 7 | (looping as u8) << 6 | (rate & 0x0F));
+    }
+    // output level 0-127, which also shifts the other channels' volume
+    // slightly (the DMC shares their mixer)
+    pub unsafe fn set_level(&self, level: u8) {
+        DMC_BASE.offset(1).write(level & 0x7F);
+    }
+    // `addr` must be $C000 or later and a multiple of 64; `len` is rounded
+    // down to a multiple of 16, plus 1, as that's what the hardware plays
+    pub unsafe fn set_sample(&self, addr: u16, len: u16) {
+        DMC_BASE
+            .offset(2)
+            .write((addr.wrapping_sub(0xC000) >> 6) as u8);
+        DMC_BASE.offset(3).write((len.saturating_sub(1) >> 4) as u8);
+    }
+    // (re)start the sample set with `set_sample`
+    pub unsafe fn start(&self) {
+        Channel::Dmc.enable(false);
+        Channel::Dmc.enable(true);
+    }
+    pub unsafe fn stop(&self) {
+        Channel::Dmc.enable(false);
+    }
+}
+
+// Sound effects are data: a list of steps for one channel, each held for
+// a number of frames. Games keep their own in ROM:
+//     const COIN: SoundEffect = SoundEffect::new(
+//         Channel::Pulse1,
+//         &[SfxStep::note(4, Note::parse("A3"), 0xbf), SfxStep::new(8, 0x1c4, 0xbf)],
+//     );
+//     const DEATH: SoundEffect = SoundEffect::new(Channel::Noise, &[..]).with_priority(10);
+//     apu.play_sfx(&COIN);
+//
+// Each channel plays one effect at a time, so effects on different
+// channels play together. An effect only replaces one of equal or lower
+// priority; a pulse effect that can't have its own channel takes the
+// other pulse if it can. While an effect plays, its channel is borrowed
+// from `nes::music`, which hands it back with its registers rewritten.
+#[derive(Copy, Clone)]
+pub enum Tone {
+    // 11-bit timer period, as is
+    Period(u16),
+    // tuned for `note::region()` when played
+    Note(Note),
+}
+
+#[derive(Copy, Clone)]
+pub struct SfxStep {
+    pub frames: u8,
+    pub tone: Tone,
+    // duty, length counter halt, constant volume and volume, as in $4000
+    pub duty_volume: u8,
+}
+impl SfxStep {
+    pub const fn new(frames: u8, period: u16, duty_volume: u8) -> Self {
+        Self {
+            frames,
+            tone: Tone::Period(period),
+            duty_volume,
+        }
+    }
+    pub const fn note(frames: u8, note: Note, duty_volume: u8) -> Self {
+        Self {
+            frames,
+            tone: Tone::Note(note),
+            duty_volume,
         }
     }
 }
 
-#[derive(PartialEq)]
-pub enum Sfx {
-    ChangeScreen,
-    MenuBoop,
-    Pause,
-    Shift,
-    Rotate,
-    Lock,
-    LevelUp,
-    Burn,
-    FourLineClear,
-    Topout,
-    None,
+pub struct SoundEffect {
+    pub channel: Channel,
+    // higher cuts off lower, 0 by default
+    pub priority: u8,
+    pub steps: &'static [SfxStep],
+}
+impl SoundEffect {
+    pub const fn new(channel: Channel, steps: &'static [SfxStep]) -> Self {
+        Self {
+            channel,
+            priority: 0,
+            steps,
+        }
+    }
+    pub const fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
 }
 
 pub fn init() {
@@ -40,136 +384,291 @@ pub fn init() {
     ]
     .iter()
     .enumerate()
-    .for_each(|(i, byte)| {
-        *APU.offset(i as _) = *byte;
+    .for_each(|(i, byte)| unsafe {
+        APU.offset(i as _).write(*byte);
     });
-    *APU.offset(0x15) = 0xF;
-    *APU.offset(0x17) = 0x40;
+    ENABLED.set(Status::PULSE_1 | Status::PULSE_2 | Status::TRIANGLE | Status::NOISE);
+    // the high period bytes were written before the channels were enabled,
+    // so their length counters are still empty: make sure the next period
+    // write goes out and loads them
+    PERIOD_HI.set([0xFF; 3]);
+    CONTROL.set([0x30, 0x30, 0x80, 0x30]);
+    unsafe {
+        STATUS.write(ENABLED.get().bits());
+        APU.offset(0x17).write(0x40);
+    }
 }
 
-fn sfx_frame(c: Channel, hi: u8, lo: u8, dcvol: u8) -> bool {
-    let p = c.addr();
+// One frame of an effect step, `first` being the effect's first frame,
+// which triggers the note; after that only changes are written, so
+// pitch changes don't restart the waveform. Pulse steps are as described
+// on `SfxStep`. On the triangle, any volume turns it on. On the noise
+// channel, the low 4 bits of a period pick the noise period and bit 7
+// the short mode, a note's low 4 bits count up to 15 as the highest
+// pitch, and the low 4 bits of `duty_volume` are the volume. The DMC
+// plays samples rather than steps and is left alone.
+fn sfx_frame(c: Channel, step: &SfxStep, first: bool) {
     unsafe {
-        p.offset(2).write(lo);
-        p.offset(3).write(hi); // only lower 3 bits matter
-        p.write(dcvol);
+        match c {
+            Channel::Pulse1 | Channel::Pulse2 => {
+                let period = match step.tone {
+                    Tone::Period(period) => period,
+                    Tone::Note(note) => note.pulse_period(note::region()),
+                };
+                write_period(c.addr(), period, first.then_some(0));
+                write_control(c.addr(), step.duty_volume);
+            }
+            Channel::Triangle => {
+                if step.duty_volume & 0x0F == 0 {
+                    TRIANGLE.silence();
+                } else {
+                    let period = match step.tone {
+                        Tone::Period(period) => period,
+                        Tone::Note(note) => note.triangle_period(note::region()),
+                    };
+                    write_period(TRIANGLE_BASE, period, first.then_some(0));
+                    TRIANGLE.on();
+                }
+            }
+            Channel::Noise => {
+                let period = match step.tone {
+                    Tone::Period(period) => period as u8,
+                    Tone::Note(note) => 0x0F - (note.0 & 0x0F),
+                };
+                NOISE.set_period(period & 0x80 != 0, period);
+                NOISE.set_volume(step.duty_volume);
+                if first {
+                    NOISE.trigger(0);
+                }
+            }
+            Channel::Dmc => {}
+        }
     }
-    true
 }
 
-fn sfx_end(c: Channel) -> bool {
+fn sfx_end(c: Channel) {
     unsafe {
-        c.addr().write(0);
+        match c {
+            Channel::Pulse1 => PULSE_1.silence(),
+            Channel::Pulse2 => PULSE_2.silence(),
+            Channel::Triangle => TRIANGLE.silence(),
+            Channel::Noise => NOISE.silence(),
+            Channel::Dmc => DMC.stop(),
+        }
     }
-    false
 }
 
 pub fn silence_all() {
     sfx_end(Channel::Pulse1);
     sfx_end(Channel::Pulse2);
+    sfx_end(Channel::Triangle);
+    sfx_end(Channel::Noise);
+    sfx_end(Channel::Dmc);
 }
 
-#[allow(dead_code)]
-fn noise_frame(tp: u8, vol: u8) -> bool {
-    unsafe {
-        APU.offset(0xC).write(vol);
-        APU.offset(0xE).write(tp);
-    }
-    true
-}
+// channels with a sound effect playing, which music leaves alone
+shadow!(static BORROWED: Status = Status(0));
 
-#[allow(dead_code)]
-fn noise_end() -> bool {
-    unsafe {
-        APU.offset(0xC).write(0b110000);
-        APU.offset(0xE).write(0);
-    }
-    false
+pub fn borrowed() -> Status {
+    BORROWED.get()
 }
 
-pub struct APU {
-    sfx: Sfx,
-    sfx_off: usize,
+// the channels effects can play on, in `APU::slots` order
+const SFX_CHANNELS: [Channel; 4] = [
+    Channel::Pulse1,
+    Channel::Pulse2,
+    Channel::Triangle,
+    Channel::Noise,
+];
+
+#[derive(Copy, Clone, Default)]
+struct Slot {
+    sfx: Option<&'static SoundEffect>,
+    step: usize,
+    // frames left of the current step
+    timer: u8,
+    // the channel's halt flag from before the effect, which its steps may
+    // change, for music to have back
+    halt: bool,
 }
-impl Default for APU {
-    fn default() -> Self {
-        Self {
-            sfx: Sfx::None,
-            sfx_off: 0,
+impl Slot {
+    fn priority(&self) -> Option<u8> {
+        self.sfx.map(|sfx| sfx.priority)
+    }
+
+    // true once the effect has finished
+    fn run(&mut self, channel: Channel) -> bool {
+        let Some(sfx) = self.sfx else {
+            return false;
+        };
+        let first = self.step == 0;
+        if self.timer == 0 {
+            if self.step >= sfx.steps.len() {
+                *self = Slot::default();
+                return true;
+            }
+            self.timer = sfx.steps[self.step].frames.max(1);
+            self.step += 1;
         }
+        sfx_frame(channel, &sfx.steps[self.step - 1], first);
+        self.timer -= 1;
+        false
     }
 }
+
+#[derive(Default)]
+pub struct APU {
+    slots: [Slot; 4],
+}
 impl APU {
-    pub fn play_sfx(&mut self, type_: Sfx) {
-        self.sfx = type_;
-        self.sfx_off = 0;
-    }
-    pub fn run_sfx(&mut self) {
-        if self.sfx == Sfx::None {
-            return;
+    // Start `sfx`, unless every channel it could use is playing something
+    // more important. Returns whether it started.
+    pub fn play_sfx(&mut self, sfx: &'static SoundEffect) -> bool {
+        let Some(first) = SFX_CHANNELS.iter().position(|&c| c == sfx.channel) else {
+            return false;
+        };
+        let allowed = |slot: &Slot| slot.priority().is_none_or(|p| p <= sfx.priority);
+        // a free pulse beats cutting off an effect on the requested one
+        let mut index = first;
+        if first < 2 && self.slots[first].sfx.is_some() {
+            let other = first ^ 1;
+            if self.slots[other].sfx.is_none() || !allowed(&self.slots[first]) {
+                index = other;
+            }
         }
-        let cont = match self.sfx {
-            Sfx::ChangeScreen | Sfx::Pause => match self.sfx_off {
-                ..=5 => sfx_frame(Channel::Pulse1, 1, 0x7C, 0b10111111),
-                ..=10 => sfx_frame(Channel::Pulse1, 1, 0xc4, 0b10111111),
-                ..=15 => sfx_frame(Channel::Pulse1, 0, 0xbf, 0b10111111),
-                _ => {
-                    // TODO: what does this do?
-                    unsafe {
-                        Channel::Pulse1.addr().offset(3).write(7);
-                    }
-                    sfx_end(Channel::Pulse1)
-                }
-            },
-            Sfx::MenuBoop => match self.sfx_off {
-                ..=2 => sfx_frame(Channel::Pulse1, 0, 0x90, 0b10110111),
-                _ => sfx_end(Channel::Pulse1),
-            },
-            Sfx::Shift => match self.sfx_off {
-                ..=2 => sfx_frame(Channel::Pulse1, 1, 0x7C, 0b10110111),
-                _ => sfx_end(Channel::Pulse1),
-            },
-            Sfx::Lock => match self.sfx_off {
-                ..=2 => sfx_frame(Channel::Pulse1, 5, 0x9d, 0b10110110),
-                ..=3 => sfx_frame(Channel::Pulse1, 6, 0xad, 0b10110110),
-                _ => sfx_end(Channel::Pulse1),
-            },
-            Sfx::Rotate => match self.sfx_off {
-                ..=1 => sfx_frame(Channel::Pulse1, 1, 0x7c, 0b10110110),
-                ..=3 => sfx_frame(Channel::Pulse1, 2, 0x1A, 0b10110000),
-                ..=5 => sfx_frame(Channel::Pulse1, 1, 0x7c, 0b10110110),
-                _ => sfx_end(Channel::Pulse1),
+        let slot = &mut self.slots[index];
+        if !allowed(slot) {
+            return false;
+        }
+        let channel = SFX_CHANNELS[index];
+        *slot = Slot {
+            sfx: Some(sfx),
+            step: 0,
+            timer: 0,
+            // an effect cut short already took the flag
+            halt: if slot.sfx.is_some() {
+                slot.halt
+            } else {
+                channel.is_halted()
             },
-            Sfx::Burn | Sfx::FourLineClear | Sfx::LevelUp => {
-                const NOTES: &[u8] = &[0xfb, 0xc4, 0x93, 0x67, 0x3f, 0x1c];
-
-                if self.sfx_off / 4 >= NOTES.len() {
-                    unsafe {
-                        Channel::Pulse1.addr().offset(3).write(7);
-                    }
-                    sfx_end(Channel::Pulse1)
-                } else {
-                    sfx_frame(Channel::Pulse1, 1, NOTES[self.sfx_off / 4], 0b10111111)
+        };
+        BORROWED.set(BORROWED.get() | channel.status());
+        true
+    }
+
+    // Once per frame, after `Music::tick`.
+    pub fn run_sfx(&mut self) {
+        for (slot, &channel) in self.slots.iter_mut().zip(SFX_CHANNELS.iter()) {
+            let halt = slot.halt;
+            if slot.run(channel) {
+                sfx_end(channel);
+                unsafe {
+                    channel.set_halt(halt);
                 }
+                BORROWED.set(BORROWED.get().without(channel.status()));
             }
-            Sfx::Topout => match self.sfx_off {
-                ..=5 => sfx_frame(Channel::Pulse1, 4, 0x34, 0b10111110),
-                ..=15 => sfx_frame(Channel::Pulse1, 4, 0xb8, 0b10111000),
-                ..=20 => sfx_frame(Channel::Pulse1, 5, 0x4c, 0b10111110),
-                ..=25 => sfx_frame(Channel::Pulse1, 5, 0xf3, 0b10110110),
-                _ => sfx_end(Channel::Pulse1),
-            },
-            Sfx::None => unreachable!(),
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.slots.iter().any(|slot| slot.sfx.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::mock_bus;
+
+    fn high_writes() -> Vec<u8> {
+        mock_bus::with(|bus| {
+            bus.apu
+                .writes
+                .iter()
+                .filter(|&&(addr, _)| addr == 0x4003)
+                .map(|&(_, value)| value)
+                .collect()
+        })
+    }
+
+    #[test]
+    fn period_changes_keep_the_trigger_length() {
+        mock_bus::reset();
+        init();
+        mock_bus::with(|bus| bus.apu.writes.clear());
+        unsafe {
+            PULSE_1.trigger(0x1FF, 5);
+            // same high byte: not written again
+            PULSE_1.set_period(0x1F0);
+            PULSE_1.set_period(0x200);
+        }
+        assert_eq!(high_writes(), [5 << 3 | 1, 5 << 3 | 2]);
+    }
+
+    #[test]
+    fn note_steps_play_at_the_region_period() {
+        const A4: SoundEffect = SoundEffect::new(
+            Channel::Pulse1,
+            &[SfxStep::note(1, Note::parse("A4"), 0b10111111)],
+        );
+        const LOW_A4: SoundEffect = SoundEffect::new(
+            Channel::Triangle,
+            &[SfxStep::note(1, Note::parse("A4"), 0b1)],
+        );
+        mock_bus::reset();
+        init();
+        let period = |reg: usize| {
+            mock_bus::with(|bus| u16::from_le_bytes([bus.apu.regs[reg], bus.apu.regs[reg + 1] & 7]))
         };
+        let mut apu = APU::default();
+        apu.play_sfx(&A4);
+        apu.play_sfx(&LOW_A4);
+        apu.run_sfx();
+        // 440 Hz is 253 on a pulse at NTSC speed, and the triangle an
+        // octave down at the same period
+        assert_eq!(period(0x02), 253);
+        assert_eq!(period(0x0A), 126);
+        assert_eq!(mock_bus::with(|bus| bus.apu.regs[0x00]), 0b10111111);
 
-        if cont {
-            self.sfx_off += 1;
-        } else {
-            self.sfx = Sfx::None
+        note::set_region(note::Region::Pal);
+        apu.run_sfx();
+        apu.play_sfx(&A4);
+        apu.run_sfx();
+        assert_eq!(period(0x02), 235);
+    }
+
+    #[test]
+    fn enabling_a_channel_leaves_a_finished_sample_alone() {
+        mock_bus::reset();
+        init();
+        unsafe {
+            DMC.start();
+        }
+        // the sample runs out
+        mock_bus::with(|bus| {
+            bus.apu.status &= !Status::DMC.bits();
+            bus.apu.writes.clear();
+        });
+        unsafe {
+            Channel::Pulse1.enable(true);
         }
+        mock_bus::with(|bus| {
+            assert_eq!(bus.apu.writes, [(0x4015, 0b1111)]);
+        });
     }
 
-    pub fn is_playing(&self) -> bool {
-        self.sfx != Sfx::None
+    #[test]
+    fn enabling_a_channel_leaves_a_playing_sample_alone() {
+        mock_bus::reset();
+        init();
+        unsafe {
+            DMC.start();
+            Channel::Noise.enable(false);
+        }
+        mock_bus::with(|bus| assert_eq!(bus.apu.regs[0x15], 0b10111));
     }
 }
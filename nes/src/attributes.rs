@@ -0,0 +1,139 @@
+// Attribute table: picks one of the four background palettes for each
+// 16x16 pixel (2x2 tile) area of a nametable.
+// See https://www.nesdev.org/wiki/PPU_attribute_tables
+//
+// Each byte covers a 32x32 area, two bits per quadrant, so changing one
+// quadrant is a read-modify-write. The PPU can't be read back cheaply while
+// rendering, so we keep a RAM copy and write whole bytes from that.
+use crate::{addr::Addr, ppu, ppu_buffer::BufferTrait};
+
+pub const ATTR_OFFSET: u16 = 0x3c0;
+pub const ATTR_SIZE: usize = 64;
+
+// areas are counted in 16x16 pixel units
+pub const AREAS_WIDE: u8 = 16;
+pub const AREAS_HIGH: u8 = 15;
+
+pub struct AttributeTable {
+    // $23C0, $27C0, $2BC0 or $2FC0
+    addr: u16,
+    shadow: [u8; ATTR_SIZE],
+}
+
+#[allow(clippy::missing_safety_doc)]
+impl AttributeTable {
+    // `nametable` is the nametable base address, e.g. $2000
+    pub const fn new(nametable: u16) -> Self {
+        Self {
+            addr: nametable + ATTR_OFFSET,
+            shadow: [0; ATTR_SIZE],
+        }
+    }
+
+    // base address of the nametable this table belongs to
+    pub fn nametable(&self) -> u16 {
+        self.addr - ATTR_OFFSET
+    }
+
+    pub fn palette(&self, x: u8, y: u8) -> u8 {
+        let (index, shift) = locate(x, y);
+        (self.shadow[index] >> shift) & 0b11
+    }
+
+    // update the shadow only, returning the byte that needs uploading
+    fn update(&mut self, x: u8, y: u8, palette: u8) -> (Addr, u8) {
+        let (index, shift) = locate(x, y);
+        let byte = &mut self.shadow[index];
+        *byte = (*byte & !(0b11 << shift)) | ((palette & 0b11) << shift);
+        (Addr(self.addr + index as u16), *byte)
+    }
+
+    // update the RAM copy only, e.g. before `write_all`
+    pub fn store(&mut self, x: u8, y: u8, palette: u8) {
+        self.update(x, y, palette);
+    }
+
+    // write straight to the PPU; rendering must be off
+    pub unsafe fn set_palette(&mut self, x: u8, y: u8, palette: u8) {
+        let (addr, byte) = self.update(x, y, palette);
+        ppu::write_addr(addr.addr());
+        ppu::write_data(byte);
+    }
+
+    // queue the write to happen during the next NMI
+    pub fn queue_palette<B: BufferTrait<N>, const N: usize>(&mut self, x: u8, y: u8, palette: u8) {
+        let (addr, byte) = self.update(x, y, palette);
+        B::tile(addr, byte);
+    }
+
+    // set every area to `palette`; call `write_all` to upload
+    pub fn fill(&mut self, palette: u8) {
+        let palette = palette & 0b11;
+        let byte = palette | palette << 2 | palette << 4 | palette << 6;
+        self.shadow = [byte; ATTR_SIZE];
+    }
+
+    // upload the whole table; rendering must be off
+    pub unsafe fn write_all(&self) {
+        ppu::write_bytes(self.addr, &self.shadow);
+    }
+
+    pub fn bytes(&self) -> &[u8; ATTR_SIZE] {
+        &self.shadow
+    }
+}
+
+// byte index and bit shift for the 16x16 area at (x, y)
+fn locate(x: u8, y: u8) -> (usize, u8) {
+    assert!(
+        x < AREAS_WIDE && y < AREAS_HIGH,
+        "attributes: area off the nametable"
+    );
+    let index = (y as usize >> 1) * 8 + (x as usize >> 1);
+    let shift = ((y & 1) << 2) | ((x & 1) << 1);
+    (index, shift)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_bus;
+
+    #[test]
+    fn corners() {
+        // top left, top right, bottom left, bottom right of a byte
+        assert_eq!(locate(0, 0), (0, 0));
+        assert_eq!(locate(1, 0), (0, 2));
+        assert_eq!(locate(0, 1), (0, 4));
+        assert_eq!(locate(1, 1), (0, 6));
+        // and of the nametable; the last row of bytes is half used
+        assert_eq!(locate(15, 0), (7, 2));
+        assert_eq!(locate(0, 14), (56, 0));
+        assert_eq!(locate(15, 14), (63, 2));
+    }
+
+    #[test]
+    #[should_panic(expected = "off the nametable")]
+    fn past_the_last_column() {
+        locate(AREAS_WIDE, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "off the nametable")]
+    fn past_the_last_row() {
+        locate(0, AREAS_HIGH);
+    }
+
+    #[test]
+    fn set_palette_keeps_the_other_quadrants() {
+        mock_bus::reset();
+        let mut table = AttributeTable::new(0x2400);
+        unsafe {
+            table.set_palette(15, 14, 3);
+            table.set_palette(14, 14, 1);
+        }
+        assert_eq!(table.palette(15, 14), 3);
+        assert_eq!(table.palette(14, 14), 1);
+        mock_bus::with(|bus| assert_eq!(bus.ppu.peek(0x27FF), 0b1101));
+    }
+}
@@ -0,0 +1,66 @@
+// Backend for every CPU bus access made through `Addr`.
+//
+// On the mos target reads and writes are volatile pointer accesses. On any
+// other target (i.e. `cargo test` on a dev machine) they are routed to a
+// simulated memory map, see `mock_bus`.
+
+#[cfg(target_arch = "mos")]
+#[inline(always)]
+pub fn ptr(addr: u16) -> *mut u8 {
+    addr as *mut u8
+}
+
+#[cfg(target_arch = "mos")]
+#[inline(always)]
+pub unsafe fn read(addr: u16) -> u8 {
+    ptr(addr).read_volatile()
+}
+
+#[cfg(target_arch = "mos")]
+#[inline(always)]
+pub unsafe fn write(addr: u16, value: u8) {
+    ptr(addr).write_volatile(value)
+}
+
+#[cfg(not(target_arch = "mos"))]
+pub use crate::mock_bus::{ptr, read, write};
+
+// RAM copies of values the CPU can't read back, like PPUCTRL or the APU's
+// enable bits:
+//     shadow!(static CTRL: Ctrl = Ctrl(0));
+//     CTRL.set(CTRL.get() | Ctrl::NMI);
+// On the NES that's a plain static. On the host each thread gets its own
+// copy, like its own `mock_bus`, so tests can run in parallel.
+macro_rules! shadow {
+    (static $name:ident: $ty:ty = $init:expr) => {
+        #[cfg(target_arch = "mos")]
+        static $name: $crate::bus::Shadow<$ty> = $crate::bus::Shadow::new($init);
+        #[cfg(not(target_arch = "mos"))]
+        $crate::mock_bus::thread_local! {
+            static $name: ::core::cell::Cell<$ty> = const { ::core::cell::Cell::new($init) };
+        }
+    };
+}
+pub(crate) use shadow;
+
+#[cfg(target_arch = "mos")]
+pub struct Shadow<T>(core::cell::Cell<T>);
+
+// there's only ever the one thread
+#[cfg(target_arch = "mos")]
+unsafe impl<T> Sync for Shadow<T> {}
+
+#[cfg(target_arch = "mos")]
+impl<T: Copy> Shadow<T> {
+    pub const fn new(value: T) -> Self {
+        Self(core::cell::Cell::new(value))
+    }
+    #[inline(always)]
+    pub fn get(&self) -> T {
+        self.0.get()
+    }
+    #[inline(always)]
+    pub fn set(&self, value: T) {
+        self.0.set(value)
+    }
+}
@@ -11,18 +11,21 @@ pub struct CappedVec<T, const N: usize> {
 }
 
 impl<T, const N: usize> CappedVec<T, N> {
+    #[allow(clippy::new_without_default)]
     pub const fn new() -> Self {
         Self {
             arr: unsafe { MaybeUninit::uninit().assume_init() },
             len: 0,
         }
     }
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         self.len
     }
     pub fn clear(&mut self) {
         self.len = 0;
     }
+    #[allow(clippy::result_unit_err)]
     pub fn try_push(&mut self, x: T) -> Result<(), ()> {
         if self.len < N {
             self.arr[self.len].write(x);
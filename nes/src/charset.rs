@@ -0,0 +1,132 @@
+// Mapping from characters to CHR tile indices.
+//
+// `ASCII` matches the usual font layout where tile 0 is ' ' and the rest of
+// printable ASCII follows in order. Games with a different CHR layout build
+// their own table:
+//     const FONT: Charset = Charset::empty(0)
+//         .with_range('0', '9', 0x30)
+//         .with_range('A', 'Z', 0x40)
+//         .with_str(".!?", 0x5A);
+//
+// Text known at compile time should go through `text!`, which converts it
+// to tile bytes during compilation and fails to build if a character is
+// missing from the charset:
+//     Buffer::draw_tiles(addr, text!(FONT, "GAME OVER"));
+
+#[derive(Copy, Clone)]
+pub struct Charset {
+    tiles: [u8; 128],
+    // one bit per ASCII code
+    present: [u8; 16],
+    // drawn for unknown characters at runtime
+    blank: u8,
+}
+
+pub const ASCII: Charset = Charset::empty(0).with_range(' ', '~', 0);
+
+impl Charset {
+    pub const fn empty(blank: u8) -> Self {
+        Self {
+            tiles: [0; 128],
+            present: [0; 16],
+            blank,
+        }
+    }
+
+    pub const fn with(mut self, ch: char, tile: u8) -> Self {
+        let code = ch as usize;
+        if code >= 128 {
+            panic!("charset: only ASCII characters are supported");
+        }
+        self.tiles[code] = tile;
+        self.present[code / 8] |= 1 << (code % 8);
+        self
+    }
+
+    // `first..=last` on consecutive tiles starting at `tile`
+    pub const fn with_range(mut self, first: char, last: char, tile: u8) -> Self {
+        let mut code = first as u32;
+        while code <= last as u32 {
+            let ch = match char::from_u32(code) {
+                Some(ch) => ch,
+                None => panic!("charset: invalid range"),
+            };
+            self = self.with(ch, tile + (code - first as u32) as u8);
+            code += 1;
+        }
+        self
+    }
+
+    // each character of `chars` on consecutive tiles starting at `tile`
+    pub const fn with_str(mut self, chars: &str, tile: u8) -> Self {
+        let bytes = chars.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            self = self.with(bytes[i] as char, tile + i as u8);
+            i += 1;
+        }
+        self
+    }
+
+    pub const fn lookup(&self, ch: char) -> Option<u8> {
+        let code = ch as usize;
+        if code < 128 && self.present[code / 8] & (1 << (code % 8)) != 0 {
+            Some(self.tiles[code])
+        } else {
+            None
+        }
+    }
+
+    // for constants: fails to compile if `ch` isn't in the charset
+    pub const fn tile(&self, ch: char) -> u8 {
+        match self.lookup(ch) {
+            Some(tile) => tile,
+            None => panic!("charset: character not in charset"),
+        }
+    }
+
+    pub fn tile_or_blank(&self, ch: char) -> u8 {
+        self.lookup(ch).unwrap_or(self.blank)
+    }
+
+    // tile for a hex digit 0-F
+    pub const fn digit(&self, digit: u8) -> u8 {
+        let ch = if digit < 10 {
+            b'0' + digit
+        } else {
+            b'A' + digit - 10
+        };
+        self.tile(ch as char)
+    }
+
+    // `N` must be `text.len()`; use `text!` rather than calling this
+    pub const fn convert<const N: usize>(&self, text: &str) -> [u8; N] {
+        let bytes = text.as_bytes();
+        if bytes.len() != N {
+            panic!("charset: output size must match the text length");
+        }
+        let mut out = [0; N];
+        let mut i = 0;
+        while i < N {
+            if bytes[i] >= 128 {
+                panic!("charset: only ASCII characters are supported");
+            }
+            out[i] = self.tile(bytes[i] as char);
+            i += 1;
+        }
+        out
+    }
+}
+
+// Convert a string literal to a `&'static [u8]` of tile indices at compile
+// time, using `charset::ASCII` or the given charset constant.
+#[macro_export]
+macro_rules! text {
+    ($charset:expr, $text:expr) => {{
+        const TILES: [u8; $text.len()] = $charset.convert($text);
+        &TILES
+    }};
+    ($text:expr) => {
+        $crate::text!($crate::charset::ASCII, $text)
+    };
+}
@@ -0,0 +1,73 @@
+// DPCM sample playback on the DMC channel.
+//
+// The DMC can only read samples from $C000-$FFF9, at 64-byte boundaries,
+// and plays 16n + 1 bytes. `Sample` takes care of the alignment and the
+// `.dpcm` section from the linker script's dpcm.ld puts it in range:
+//     #[link_section = ".dpcm"]
+//     static KICK: Sample<257> = Sample(*include_bytes!("kick.dmc"));
+//     dpcm::play(&KICK, 15, false);
+//
+// `.dmc` files come from `nes::dpcm_import` (host only), or any other
+// DPCM converter.
+use crate::apu::{Channel, DMC};
+
+// NTSC sample rates in Hz for each `rate` value
+pub const RATES: [u16; 16] = [
+    4182, 4710, 5264, 5593, 6258, 7046, 7919, 8363, 9420, 11186, 12604, 13983, 16885, 21307, 24858,
+    33144,
+];
+
+#[repr(C, align(64))]
+pub struct Sample<const N: usize>(pub [u8; N]);
+
+impl<const N: usize> Sample<N> {
+    const LENGTH_OK: () = assert!(N % 16 == 1 && N <= 4081, "DPCM samples are 16n + 1 bytes");
+
+    fn addr(&self) -> u16 {
+        self.0.as_ptr() as usize as u16
+    }
+}
+
+// Start `sample`, cutting off whatever the DMC was playing. `rate` is
+// 0-15, see `RATES`.
+pub fn play<const N: usize>(sample: &'static Sample<N>, rate: u8, looping: bool) {
+    #[allow(clippy::let_unit_value)]
+    let _ = Sample::<N>::LENGTH_OK;
+    unsafe {
+        DMC.set_rate(rate, looping, false);
+        // where `dpcm_import` starts its deltas from
+        DMC.set_level(64);
+        DMC.set_sample(sample.addr(), N as u16);
+        DMC.start();
+    }
+}
+
+pub fn stop() {
+    unsafe {
+        DMC.stop();
+    }
+}
+
+// false once a non-looping sample has finished
+pub fn is_playing() -> bool {
+    Channel::Dmc.is_active()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_bus;
+
+    static SILENCE: Sample<17> = Sample([0x55; 17]);
+
+    #[test]
+    fn play_starts_from_level_64() {
+        mock_bus::reset();
+        play(&SILENCE, 15, false);
+        mock_bus::with(|bus| {
+            assert_eq!(bus.apu.regs[0x11], 64);
+            assert_eq!(bus.apu.regs[0x10], 15);
+            assert_eq!(bus.apu.regs[0x13], 1);
+        });
+    }
+}
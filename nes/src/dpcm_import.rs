@@ -0,0 +1,229 @@
+// Converts 8-bit (or 16-bit) PCM WAV files to DMC sample data for
+// `nes::dpcm`. Host only, for build scripts:
+//     let wav = fs::read("sfx/kick.wav").unwrap();
+//     let dmc = nes::dpcm_import::convert(&wav, 15).unwrap_or_else(|e| panic!("kick.wav: {e}"));
+//     fs::write(Path::new(&env::var("OUT_DIR").unwrap()).join("kick.dmc"), dmc).unwrap();
+//
+// The output is padded to 16n + 1 bytes; size the `Sample` to match.
+extern crate std;
+
+use std::{fmt, format, string::String, vec::Vec};
+
+use crate::dpcm::RATES;
+
+#[derive(Debug)]
+pub struct Error(pub String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+fn error<T>(message: impl Into<String>) -> Result<T, Error> {
+    Err(Error(message.into()))
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+// mono samples from -1.0 to 1.0, and the sample rate
+fn read_wav(wav: &[u8]) -> Result<(Vec<f32>, u32), Error> {
+    if wav.len() < 12 || &wav[0..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+        return error("not a WAV file");
+    }
+    let mut format = None;
+    let mut at = 12;
+    while at + 8 <= wav.len() {
+        let id = &wav[at..at + 4];
+        let size = u32_at(wav, at + 4) as usize;
+        let body = &wav[at + 8..(at + 8 + size).min(wav.len())];
+        if id == b"fmt " {
+            if body.len() < 16 {
+                return error("fmt chunk is too short");
+            }
+            if u16_at(body, 0) != 1 {
+                return error("only uncompressed PCM is supported");
+            }
+            let channels = u16_at(body, 2) as usize;
+            let rate = u32_at(body, 4);
+            let bits = u16_at(body, 14);
+            if channels == 0 || (bits != 8 && bits != 16) {
+                return error(format!("{bits}-bit audio isn't supported, use 8 or 16"));
+            }
+            format = Some((channels, rate, bits));
+        } else if id == b"data" {
+            let Some((channels, rate, bits)) = format else {
+                return error("data chunk before fmt chunk");
+            };
+            let width = bits as usize / 8;
+            let frames = body.len() / (width * channels);
+            let mut samples = Vec::with_capacity(frames);
+            for frame in 0..frames {
+                let mut sum = 0.0;
+                for channel in 0..channels {
+                    let at = (frame * channels + channel) * width;
+                    sum += if width == 1 {
+                        // 8-bit WAV is unsigned
+                        (body[at] as f32 - 128.0) / 128.0
+                    } else {
+                        u16_at(body, at) as i16 as f32 / 32768.0
+                    };
+                }
+                samples.push(sum / channels as f32);
+            }
+            return Ok((samples, rate));
+        }
+        // chunks are padded to an even size
+        at += 8 + size + (size & 1);
+    }
+    error("no data chunk")
+}
+
+// Convert a WAV file to DMC bytes for playback at `rate` (0-15). The
+// output starts from level 64, the DMC's usual resting point.
+pub fn convert(wav: &[u8], rate: u8) -> Result<Vec<u8>, Error> {
+    let Some(&dmc_rate) = RATES.get(rate as usize) else {
+        return error(format!("rate {rate} is out of range, 0-15"));
+    };
+    let (samples, wav_rate) = read_wav(wav)?;
+    if samples.is_empty() {
+        return error("WAV file has no samples");
+    }
+
+    let step = wav_rate as f32 / dmc_rate as f32;
+    let bits = (samples.len() as f32 / step) as usize;
+    let mut out = Vec::with_capacity(bits / 8 + 17);
+    let mut level: i32 = 64;
+    let mut byte = 0u8;
+    for bit in 0..bits {
+        // linear interpolation between the nearest input samples
+        let pos = bit as f32 * step;
+        let i = pos as usize;
+        let next = samples.get(i + 1).copied().unwrap_or(samples[i]);
+        let sample = samples[i] + (next - samples[i]) * (pos - i as f32);
+        let target = (64.0 + sample * 63.0) as i32;
+
+        // each bit moves the output level up or down by 2, least
+        // significant bit first, unless that would leave 0-127
+        if target > level {
+            byte |= 1 << (bit % 8);
+            if level <= 125 {
+                level += 2;
+            }
+        } else if level >= 2 {
+            level -= 2;
+        }
+        if bit % 8 == 7 {
+            out.push(byte);
+            byte = 0;
+        }
+    }
+    if !bits.is_multiple_of(8) {
+        out.push(byte);
+    }
+    // pad with alternating bits, which hold the level where it is
+    while out.len() % 16 != 1 {
+        out.push(0x55);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 16-bit mono at `rate` 15's own rate, so each sample is one bit
+    fn wav(samples: &[i16]) -> Vec<u8> {
+        let data = samples.len() as u32 * 2;
+        let rate = RATES[15] as u32;
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + data).to_le_bytes());
+        out.extend_from_slice(b"WAVEfmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&rate.to_le_bytes());
+        out.extend_from_slice(&(rate * 2).to_le_bytes());
+        out.extend_from_slice(&2u16.to_le_bytes());
+        out.extend_from_slice(&16u16.to_le_bytes());
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&data.to_le_bytes());
+        for sample in samples {
+            out.extend_from_slice(&sample.to_le_bytes());
+        }
+        out
+    }
+
+    // the output levels the DMC plays, starting from 64
+    fn decode(dmc: &[u8], bits: usize) -> Vec<i32> {
+        let mut level = 64;
+        (0..bits)
+            .map(|bit| {
+                if dmc[bit / 8] & 1 << (bit % 8) != 0 {
+                    if level <= 125 {
+                        level += 2;
+                    }
+                } else if level >= 2 {
+                    level -= 2;
+                }
+                level
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bits_follow_the_signal() {
+        // a step up from the middle, then back
+        let mut samples = [0i16; 32];
+        samples[8..24].fill(i16::MAX / 4);
+        let dmc = convert(&wav(&samples), 15).unwrap();
+        // holding the middle alternates down and up, least significant
+        // bit first
+        assert_eq!(dmc[0], 0b1010_1010);
+        // a quarter of full scale is 15-16 levels up: 8 steps of 2
+        assert_eq!(dmc[1], 0xFF);
+        assert_eq!(dmc[3], 0x00);
+        let levels = decode(&dmc, 32);
+        assert_eq!(levels[15], 80);
+        assert_eq!(levels[31], 64);
+    }
+
+    #[test]
+    fn levels_stay_in_range() {
+        let mut samples = [i16::MAX; 200];
+        samples[80..].fill(i16::MIN);
+        let dmc = convert(&wav(&samples), 15).unwrap();
+        let levels = decode(&dmc, 200);
+        // full scale holds at the limits instead of stepping past them
+        assert!(levels[40..80].iter().all(|&level| level >= 124));
+        assert!(levels[150..].iter().all(|&level| level <= 2));
+    }
+
+    #[test]
+    fn output_is_padded_to_16n_plus_1() {
+        for len in [1, 8, 9, 128, 129, 200] {
+            let dmc = convert(&wav(&std::vec![0; len]), 15).unwrap();
+            assert_eq!(dmc.len() % 16, 1, "{len} samples");
+            let data = len.div_ceil(8);
+            assert!(dmc.len() >= data);
+            assert!(dmc[data..].iter().all(|&byte| byte == 0x55));
+        }
+    }
+
+    #[test]
+    fn bad_input_is_an_error() {
+        assert!(convert(b"not a wav", 15).is_err());
+        assert!(convert(&wav(&[0; 8]), 16).is_err());
+        assert!(convert(&wav(&[]), 15).is_err());
+        let mut mono24 = wav(&[0; 8]);
+        mono24[34] = 24;
+        assert!(convert(&mono24, 15).unwrap_err().0.contains("24-bit"));
+    }
+}
@@ -1,5 +1,6 @@
-use crate::addr::Addr;
+use crate::{addr::Addr, bus::shadow};
 // need to import at least one C function to force the linker to work (?)
+#[cfg(target_arch = "mos")]
 extern "C" {
     fn wait_vblank();
 }
@@ -17,7 +18,12 @@ pub enum Button {
 }
 
 pub fn wait_for_vblank() {
-    unsafe { wait_vblank() };
+    #[cfg(target_arch = "mos")]
+    unsafe {
+        wait_vblank()
+    };
+    #[cfg(not(target_arch = "mos"))]
+    crate::mock_bus::vblank();
     // Addr(0x80).write(0);
     // while Addr(0x80).read() == 0 {
 
@@ -26,7 +32,7 @@ pub fn wait_for_vblank() {
 
 pub fn _set_chr_bank(bank: u8) {
     unsafe {
-        *(0x8000 as *mut u8) = bank;
+        Addr(0x8000).write(bank);
     }
 }
 
@@ -53,31 +59,129 @@ const fn button_code(button: Button) -> u8 {
 }
 
 static JOYPAD1: Addr = Addr(0x4016);
-static mut BUTTONS: u8 = 0;
-
-pub fn poll_controller() {
-    // TODO: https://www.nesdev.org/wiki/Controller_reading_code#DPCM_Safety_using_Repeated_Reads
+shadow!(static BUTTONS: u8 = 0);
+// last poll's `BUTTONS`
+shadow!(static PREVIOUS: u8 = 0);
+// polls each button has been down for, by bit, stopping at 255
+shadow!(static HELD: [u8; 8] = [0; 8]);
 
+fn read_joypad() -> u8 {
+    let mut buttons = 0;
     unsafe {
         JOYPAD1.write(1);
         JOYPAD1.write(0);
 
         for _ in 0..8 {
             let a = JOYPAD1.read();
-            BUTTONS <<= 1;
-            BUTTONS |= a & 1;
+            buttons <<= 1;
+            buttons |= a & 1;
         }
     }
+    buttons
+}
+
+// once per frame
+pub fn poll_controller() {
+    PREVIOUS.set(BUTTONS.get());
+    // A DMC sample fetch during a read can clock the joypad an extra time
+    // and lose a bit, so read until two reads agree. See
+    // https://www.nesdev.org/wiki/Controller_reading_code#DPCM_Safety_using_Repeated_Reads
+    let mut buttons = read_joypad();
+    loop {
+        let again = read_joypad();
+        if again == buttons {
+            break;
+        }
+        buttons = again;
+    }
+    BUTTONS.set(buttons);
+
+    let mut held = HELD.get();
+    for (bit, held) in held.iter_mut().enumerate() {
+        *held = if buttons & 1 << bit != 0 {
+            held.saturating_add(1)
+        } else {
+            0
+        };
+    }
+    HELD.set(held);
 }
 
 pub fn controller_buttons() -> u8 {
-    unsafe { BUTTONS }
+    BUTTONS.get()
 }
 
 pub fn is_pressed(button: Button) -> bool {
     controller_buttons() & button_code(button) != 0
 }
 
+// buttons that went down since the last poll
+pub fn pressed_buttons() -> u8 {
+    BUTTONS.get() & !PREVIOUS.get()
+}
+
+// buttons that came up since the last poll
+pub fn released_buttons() -> u8 {
+    PREVIOUS.get() & !BUTTONS.get()
+}
+
+pub fn just_pressed(button: Button) -> bool {
+    pressed_buttons() & button_code(button) != 0
+}
+
+pub fn just_released(button: Button) -> bool {
+    released_buttons() & button_code(button) != 0
+}
+
+// 1 on the frame `button` goes down, counting up to 255 while it's held,
+// 0 while it's up
+pub fn held_frames(button: Button) -> u8 {
+    let bit = button_code(button).trailing_zeros() as usize;
+    HELD.get()[bit]
+}
+
+// Auto-repeat for menus, like a keyboard's: a button fires when pressed,
+// again after `delay` frames held, then every `rate` frames.
+//     static mut CURSOR_REPEAT: Repeat = Repeat::new(16, 6);
+//     let fired = CURSOR_REPEAT.update(io::UP | io::DOWN);
+//     if fired & io::UP != 0 { .. }
+pub struct Repeat {
+    delay: u8,
+    rate: u8,
+    // frames until the held buttons fire again
+    timer: u8,
+}
+impl Repeat {
+    pub const fn new(delay: u8, rate: u8) -> Self {
+        Self {
+            delay,
+            rate,
+            timer: 0,
+        }
+    }
+
+    // Once per frame after `poll_controller`. Returns the buttons out of
+    // `buttons` that fire this frame. A new press restarts the delay.
+    pub fn update(&mut self, buttons: u8) -> u8 {
+        let held = controller_buttons() & buttons;
+        let pressed = pressed_buttons() & buttons;
+        if pressed != 0 {
+            self.timer = self.delay;
+            return pressed;
+        }
+        if held == 0 {
+            return 0;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.rate.max(1);
+            held
+        } else {
+            0
+        }
+    }
+}
+
 pub fn byte_to_digits(b: u8) -> [u8; 2] {
     let d1 = b / 0x10;
     let d0 = b % 0x10;
@@ -89,11 +193,76 @@ pub fn u16_to_digits(x: u16) -> [u8; 4] {
     [d32[1], d32[0], d10[1], d10[0]]
 }
 
+// for tiles use `Charset::digit` instead
 pub fn digit_to_ascii(b: u8) -> u8 {
     if b < 10 {
-        65 - 19 + b + 2
+        b'0' + b
     } else {
-        65 - 2 + b - 10 + 2
-        //0x60
+        b'A' + b - 10
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_bus;
+
+    fn poll(buttons: u8) {
+        mock_bus::set_buttons(buttons);
+        poll_controller();
+    }
+
+    #[test]
+    fn a_button_down_on_the_first_frame_is_a_press() {
+        mock_bus::reset();
+        poll(A);
+        assert!(is_pressed(Button::A));
+        assert!(just_pressed(Button::A));
+        assert_eq!(held_frames(Button::A), 1);
+        poll(A);
+        assert!(!just_pressed(Button::A));
+        assert_eq!(held_frames(Button::A), 2);
+    }
+
+    #[test]
+    fn releases_are_edges() {
+        mock_bus::reset();
+        poll(A | B);
+        poll(B);
+        assert!(just_released(Button::A));
+        assert!(!just_released(Button::B));
+        assert_eq!(released_buttons(), A);
+        assert_eq!(held_frames(Button::A), 0);
+        poll(B);
+        assert_eq!(released_buttons(), 0);
+    }
+
+    #[test]
+    fn held_frames_stop_at_255() {
+        mock_bus::reset();
+        for _ in 0..300 {
+            poll(UP);
+        }
+        assert_eq!(held_frames(Button::Up), 255);
+        assert_eq!(held_frames(Button::Down), 0);
+    }
+
+    #[test]
+    fn repeat_fires_after_the_delay_then_at_the_rate() {
+        mock_bus::reset();
+        let mut repeat = Repeat::new(3, 2);
+        let mut fired = [0; 9];
+        for frame in &mut fired {
+            poll(DOWN);
+            *frame = repeat.update(UP | DOWN);
+        }
+        assert_eq!(fired, [DOWN, 0, 0, DOWN, 0, DOWN, 0, DOWN, 0]);
+        // letting go and pressing again restarts the delay
+        poll(0);
+        assert_eq!(repeat.update(UP | DOWN), 0);
+        poll(DOWN);
+        assert_eq!(repeat.update(UP | DOWN), DOWN);
+        poll(DOWN);
+        assert_eq!(repeat.update(UP | DOWN), 0);
     }
 }
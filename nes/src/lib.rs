@@ -1,12 +1,33 @@
 #![no_std]
 
 pub mod addr;
+pub mod animation;
 pub mod apu;
+pub mod attributes;
+pub mod bus;
 pub mod capped_vec;
+pub mod charset;
 pub mod constants;
+pub mod dpcm;
+#[cfg(not(target_arch = "mos"))]
+pub mod dpcm_import;
 pub mod io;
+pub mod metatile;
+#[cfg(not(target_arch = "mos"))]
+pub mod mock_bus;
+pub mod music;
+#[cfg(not(target_arch = "mos"))]
+pub mod music_import;
+pub mod note;
+pub mod palette;
 pub mod ppu;
 pub mod ppu_buffer;
+pub mod ppu_regs;
+pub mod rle;
+pub mod scroll;
 pub mod sprites;
+#[cfg(not(target_arch = "mos"))]
+pub mod synth;
+pub mod text_box;
 pub mod utils;
 pub mod vec2;
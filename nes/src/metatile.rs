@@ -0,0 +1,207 @@
+// Metatiles: 16x16 pixel blocks of four 8x8 tiles that share one palette,
+// i.e. exactly one attribute table area, plus game-defined collision flags.
+// Maps are grids of metatile indices, or for bigger levels grids of 32x32
+// blocks that each index four metatiles.
+//
+// Definitions and maps normally live in ROM, but a map can just as well be
+// a slice of RAM that the game edits (breakable bricks, collected coins);
+// `queue_metatile` redraws a single cell afterwards.
+use crate::{
+    addr::Addr, attributes::AttributeTable, ppu, ppu_buffer::BufferTrait, scroll::TileSource,
+};
+
+// collision flags are up to the game, this one is just the common case
+pub const SOLID: u8 = 0b1;
+
+#[derive(Copy, Clone)]
+pub struct Metatile {
+    // top left, top right, bottom left, bottom right
+    pub tiles: [u8; 4],
+    pub palette: u8,
+    pub flags: u8,
+}
+
+impl Metatile {
+    pub const fn new(tiles: [u8; 4], palette: u8, flags: u8) -> Self {
+        Self {
+            tiles,
+            palette,
+            flags,
+        }
+    }
+
+    pub const fn is_solid(&self) -> bool {
+        self.flags & SOLID != 0
+    }
+}
+
+pub struct MetatileMap<'a> {
+    metatiles: &'a [Metatile],
+    // 4x4 tile blocks as metatile indices, in the same order as `tiles`
+    blocks: &'a [[u8; 4]],
+    cells: &'a [u8],
+    // in cells: metatiles, or blocks if there are any
+    width: u16,
+    height: u16,
+}
+
+#[allow(clippy::missing_safety_doc)]
+impl<'a> MetatileMap<'a> {
+    // each cell is an index into `metatiles`
+    pub const fn new(metatiles: &'a [Metatile], cells: &'a [u8], width: u16, height: u16) -> Self {
+        Self {
+            metatiles,
+            blocks: &[],
+            cells,
+            width,
+            height,
+        }
+    }
+
+    // each cell is an index into `blocks` instead
+    pub const fn with_blocks(mut self, blocks: &'a [[u8; 4]]) -> Self {
+        self.blocks = blocks;
+        self
+    }
+
+    fn scale(&self) -> u16 {
+        if self.blocks.is_empty() {
+            1
+        } else {
+            2
+        }
+    }
+
+    // size in metatiles
+    pub fn width(&self) -> u16 {
+        self.width * self.scale()
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height * self.scale()
+    }
+
+    // the metatile at (x, y) in 16x16 units
+    pub fn metatile(&self, x: u16, y: u16) -> Option<&'a Metatile> {
+        if x >= self.width() || y >= self.height() {
+            return None;
+        }
+        let index = if self.blocks.is_empty() {
+            self.cells[(y * self.width + x) as usize]
+        } else {
+            let block = self.cells[((y / 2) * self.width + x / 2) as usize];
+            self.blocks[block as usize][((y & 1) * 2 + (x & 1)) as usize]
+        };
+        self.metatiles.get(index as usize)
+    }
+
+    // the metatile under a pixel, None outside the map
+    pub fn metatile_at(&self, x: u16, y: u16) -> Option<&'a Metatile> {
+        self.metatile(x / 16, y / 16)
+    }
+
+    // Draw the screen whose top left metatile is (x, y) to the nametable
+    // that `attributes` belongs to. Rendering must be off.
+    #[inline(never)]
+    pub unsafe fn draw(&self, x: u16, y: u16, attributes: &mut AttributeTable) {
+        let nametable = attributes.nametable();
+        for row in 0..30 {
+            ppu::write_addr(nametable + row * 32);
+            for column in 0..32 {
+                ppu::write_data(self.tile(x * 2 + column, y * 2 + row));
+            }
+        }
+        for ay in 0..15 {
+            for ax in 0..16 {
+                if let Some(metatile) = self.metatile(x + ax, y + ay) {
+                    attributes.store(ax as u8, ay as u8, metatile.palette);
+                }
+            }
+        }
+        attributes.write_all();
+    }
+}
+
+// out of bounds reads as tile 0
+impl TileSource for MetatileMap<'_> {
+    fn tile(&self, x: u16, y: u16) -> u8 {
+        match self.metatile(x / 2, y / 2) {
+            Some(metatile) => metatile.tiles[((y & 1) * 2 + (x & 1)) as usize],
+            None => 0,
+        }
+    }
+}
+
+// Queue `metatile` at screen position (x, y) in 16x16 units, e.g. after
+// changing that map cell. Costs 14 bytes of buffer.
+pub fn queue_metatile<B: BufferTrait<N>, const N: usize>(
+    metatile: &Metatile,
+    x: u8,
+    y: u8,
+    attributes: &mut AttributeTable,
+) {
+    let addr = attributes.nametable() + x as u16 * 2 + y as u16 * 64;
+    B::tiles(Addr(addr), metatile.tiles[..2].iter().copied());
+    B::tiles(Addr(addr + 32), metatile.tiles[2..].iter().copied());
+    attributes.queue_palette::<B, N>(x, y, metatile.palette);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // metatile n has tiles 4n..4n+4 and palette n & 3
+    const METATILES: [Metatile; 8] = {
+        let mut metatiles = [Metatile::new([0; 4], 0, 0); 8];
+        let mut n = 0;
+        while n < 8 {
+            let t = n as u8 * 4;
+            metatiles[n] = Metatile::new([t, t + 1, t + 2, t + 3], n as u8 & 3, 0);
+            n += 1;
+        }
+        metatiles
+    };
+
+    fn index(metatile: Option<&Metatile>) -> Option<u8> {
+        metatile.map(|metatile| metatile.tiles[0] / 4)
+    }
+
+    #[test]
+    fn plain_map_edges() {
+        // 3x2
+        let map = MetatileMap::new(&METATILES, &[0, 1, 2, 3, 4, 5], 3, 2);
+        assert_eq!(index(map.metatile_at(15, 15)), Some(0));
+        assert_eq!(index(map.metatile_at(16, 15)), Some(1));
+        assert_eq!(index(map.metatile_at(15, 16)), Some(3));
+        // last column and row
+        assert_eq!(index(map.metatile_at(47, 31)), Some(5));
+        assert_eq!(index(map.metatile_at(48, 0)), None);
+        assert_eq!(index(map.metatile_at(0, 32)), None);
+        // tiles within the last metatile, then nothing past it
+        assert_eq!(map.tile(5, 3), 5 * 4 + 3);
+        assert_eq!(map.tile(6, 0), 0);
+    }
+
+    #[test]
+    fn blocks_split_into_four_metatiles() {
+        const BLOCKS: [[u8; 4]; 2] = [[0, 1, 2, 3], [4, 5, 6, 7]];
+        // 2x2 blocks, so 4x4 metatiles
+        let map = MetatileMap::new(&METATILES, &[0, 1, 1, 0], 2, 2).with_blocks(&BLOCKS);
+        assert_eq!((map.width(), map.height()), (4, 4));
+        assert_eq!(index(map.metatile(1, 1)), Some(3));
+        assert_eq!(index(map.metatile(2, 1)), Some(6));
+        assert_eq!(index(map.metatile(1, 2)), Some(5));
+        // last column and row
+        assert_eq!(index(map.metatile(3, 3)), Some(3));
+        assert_eq!(index(map.metatile(4, 3)), None);
+        assert_eq!(index(map.metatile(3, 4)), None);
+        assert_eq!(index(map.metatile_at(63, 32)), Some(1));
+    }
+
+    #[test]
+    fn indices_past_the_metatiles_are_empty() {
+        let map = MetatileMap::new(&METATILES, &[8], 1, 1);
+        assert!(map.metatile(0, 0).is_none());
+        assert_eq!(map.tile(0, 0), 0);
+    }
+}
@@ -0,0 +1,488 @@
+// Host-side stand-in for the NES CPU bus so the crate can run under
+// `cargo test`. Plain memory covers the whole 64 KiB map; the PPU registers
+// ($2000-$2007, mirrored up to $3FFF), the APU/IO registers ($4000-$4017)
+// and OAM DMA ($4014) are modelled closely enough for tests to inspect what
+// a frame wrote to VRAM, OAM and the sound registers.
+//
+// Every test thread gets its own bus, and its own copy of the register
+// shadows (see `bus::shadow!`), so tests can run in parallel.
+extern crate std;
+
+use std::{boxed::Box, vec::Vec};
+
+// for `bus::shadow!`
+pub use std::thread_local;
+
+use crate::ppu::{Ctrl, Mask, Mirroring, Status};
+
+pub struct MockPpu {
+    pub ctrl: u8,
+    pub mask: u8,
+    pub status: u8,
+    pub oam_addr: u8,
+    pub oam: [u8; 0x100],
+    // $0000-$3FFF as seen from the PPU side
+    pub vram: [u8; 0x4000],
+    pub mirroring: Mirroring,
+    pub scroll_x: u8,
+    pub scroll_y: u8,
+    pub vram_addr: u16,
+    // between `vblank()` and the first PPUSTATUS read after it
+    in_vblank: bool,
+    // shared $2005/$2006 write toggle
+    latch: bool,
+    addr_high: u8,
+    read_buffer: u8,
+}
+
+impl MockPpu {
+    fn new() -> Self {
+        Self {
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            oam: [0; 0x100],
+            vram: [0; 0x4000],
+            mirroring: Mirroring::Vertical,
+            scroll_x: 0,
+            scroll_y: 0,
+            vram_addr: 0,
+            in_vblank: false,
+            latch: false,
+            addr_high: 0,
+            read_buffer: 0,
+        }
+    }
+
+    // resolve nametable and palette mirrors to an index into `vram`
+    pub fn map(&self, addr: u16) -> usize {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0x2000..=0x3EFF => {
+                let offset = addr & 0x03FF;
+                let table = (addr >> 10) & 3;
+                let table = match self.mirroring {
+                    Mirroring::Horizontal => table & 2,
+                    Mirroring::Vertical => table & 1,
+                    Mirroring::FourScreen => table,
+                };
+                (0x2000 + table * 0x400 + offset) as usize
+            }
+            0x3F00..=0x3FFF => {
+                let entry = addr & 0x1F;
+                // sprite backdrop entries mirror the background ones
+                let entry = match entry {
+                    0x10 | 0x14 | 0x18 | 0x1C => entry - 0x10,
+                    _ => entry,
+                };
+                (0x3F00 + entry) as usize
+            }
+            _ => addr as usize,
+        }
+    }
+
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.vram[self.map(addr)]
+    }
+
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        let index = self.map(addr);
+        self.vram[index] = value;
+    }
+
+    fn increment(&mut self) {
+        let step = if Ctrl(self.ctrl).contains(Ctrl::VRAM_INC_32) {
+            32
+        } else {
+            1
+        };
+        self.vram_addr = self.vram_addr.wrapping_add(step) & 0x3FFF;
+    }
+
+    fn read(&mut self, reg: u16) -> u8 {
+        match reg {
+            2 => {
+                let value = self.status;
+                self.status &= !Status::VBLANK.bits();
+                self.latch = false;
+                self.advance();
+                value
+            }
+            4 => self.oam[self.oam_addr as usize],
+            7 => {
+                let addr = self.vram_addr;
+                let value = if addr >= 0x3F00 {
+                    // palette reads aren't buffered, the buffer gets the
+                    // nametable byte "underneath" instead
+                    self.read_buffer = self.peek(addr - 0x1000);
+                    self.peek(addr)
+                } else {
+                    let value = self.read_buffer;
+                    self.read_buffer = self.peek(addr);
+                    value
+                };
+                self.increment();
+                value
+            }
+            // write-only registers read back as open bus
+            _ => 0,
+        }
+    }
+
+    // There's no scanline timing, so each PPUSTATUS read moves time along
+    // instead: first out of vblank (clearing the flags as the pre-render line
+    // does), then on to sprite 0 if it can hit. That's enough for polling
+    // loops like `ppu::wait_sprite_zero` to finish.
+    fn advance(&mut self) {
+        let hit = Status::SPRITE_0_HIT.bits();
+        if self.in_vblank {
+            self.in_vblank = false;
+            self.status &= !(Status::VBLANK.bits() | hit);
+        } else if self.status & hit == 0 && self.sprite_zero_visible() {
+            self.status |= hit;
+        }
+    }
+
+    fn sprite_zero_visible(&self) -> bool {
+        let both = Mask::SHOW_BG | Mask::SHOW_SPRITES;
+        Mask(self.mask).contains(both) && self.oam[0] < 0xEF
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        match reg {
+            0 => self.ctrl = value,
+            1 => self.mask = value,
+            3 => self.oam_addr = value,
+            4 => {
+                self.oam[self.oam_addr as usize] = value;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            5 => {
+                if self.latch {
+                    self.scroll_y = value;
+                } else {
+                    self.scroll_x = value;
+                }
+                self.latch = !self.latch;
+            }
+            6 => {
+                if self.latch {
+                    self.vram_addr = ((self.addr_high as u16) << 8 | value as u16) & 0x3FFF;
+                } else {
+                    self.addr_high = value;
+                }
+                self.latch = !self.latch;
+            }
+            7 => {
+                self.poke(self.vram_addr, value);
+                self.increment();
+            }
+            _ => {}
+        }
+    }
+}
+
+pub struct MockApu {
+    // $4000-$4017, last value written
+    pub regs: [u8; 0x18],
+    // every write in order, for sequencing tests
+    pub writes: Vec<(u16, u8)>,
+    // What $4015 reads back. Nothing counts down here, so it's the enable
+    // bits as last written; clear one to have that channel finish.
+    pub status: u8,
+}
+
+pub struct MockJoypad {
+    // bit layout matches `io::A` .. `io::RIGHT`
+    pub buttons: u8,
+    strobe: bool,
+    shift: u8,
+}
+
+pub struct MockBus {
+    pub ram: [u8; 0x10000],
+    pub ppu: MockPpu,
+    pub apu: MockApu,
+    pub joypad: MockJoypad,
+    pub frame: u32,
+    // called on every simulated vblank, like the real NMI vector
+    pub nmi: Option<fn()>,
+}
+
+impl MockBus {
+    pub fn new() -> Self {
+        Self {
+            ram: [0; 0x10000],
+            ppu: MockPpu::new(),
+            apu: MockApu {
+                regs: [0; 0x18],
+                writes: Vec::new(),
+                status: 0,
+            },
+            joypad: MockJoypad {
+                buttons: 0,
+                strobe: false,
+                shift: 0,
+            },
+            frame: 0,
+            nmi: None,
+        }
+    }
+
+    pub fn read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x2000..=0x3FFF => self.ppu.read(addr & 7),
+            0x4015 => self.apu.status,
+            0x4016 => {
+                let joypad = &mut self.joypad;
+                if joypad.strobe {
+                    joypad.shift = joypad.buttons;
+                }
+                let value = joypad.shift >> 7;
+                // an official pad returns 1 once all 8 bits are shifted out
+                joypad.shift = (joypad.shift << 1) | 1;
+                value
+            }
+            0x4000..=0x4017 => 0,
+            _ => self.ram[addr as usize],
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x2000..=0x3FFF => self.ppu.write(addr & 7, value),
+            0x4014 => {
+                let page = (value as usize) << 8;
+                for i in 0..0x100 {
+                    let dest = self.ppu.oam_addr.wrapping_add(i as u8) as usize;
+                    self.ppu.oam[dest] = self.ram[page + i];
+                }
+            }
+            0x4016 => {
+                let strobe = value & 1 != 0;
+                if self.joypad.strobe && !strobe {
+                    self.joypad.shift = self.joypad.buttons;
+                }
+                self.joypad.strobe = strobe;
+            }
+            0x4000..=0x4017 => {
+                if addr == 0x4015 {
+                    self.apu.status = value & 0x1F;
+                }
+                self.apu.regs[(addr - 0x4000) as usize] = value;
+                self.apu.writes.push((addr, value));
+            }
+            _ => self.ram[addr as usize] = value,
+        }
+    }
+}
+
+impl Default for MockBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+thread_local! {
+    // leaked so that `ptr` can hand out addresses that outlive a borrow
+    static BUS: *mut MockBus = Box::into_raw(Box::new(MockBus::new()));
+}
+
+// Inspect or modify this thread's bus. Don't touch `Addr` (or anything that
+// goes through it) inside the closure.
+pub fn with<R>(f: impl FnOnce(&mut MockBus) -> R) -> R {
+    BUS.with(|bus| f(unsafe { &mut **bus }))
+}
+
+// Put this thread's bus back to power-on state
+pub fn reset() {
+    with(|bus| *bus = MockBus::new());
+}
+
+pub fn set_buttons(buttons: u8) {
+    with(|bus| bus.joypad.buttons = buttons);
+}
+
+// Simulate the start of vblank: raise the PPUSTATUS flag, set the $80 byte
+// that `nmi.c` sets and run the registered NMI handler.
+pub fn vblank() {
+    let nmi = with(|bus| {
+        bus.frame += 1;
+        bus.ppu.status |= Status::VBLANK.bits();
+        bus.ppu.in_vblank = true;
+        bus.ram[0x80] = 1;
+        bus.nmi
+    });
+    if let Some(nmi) = nmi {
+        nmi();
+    }
+}
+
+// Plain RAM only: a pointer can't see register side effects, so `Addr`
+// derefs of $2000-$401F panic instead of quietly missing them.
+pub fn ptr(addr: u16) -> *mut u8 {
+    assert!(
+        !(0x2000..0x4020).contains(&addr),
+        "mock_bus: ${addr:04X} is a register, use `Addr::read`/`Addr::write`"
+    );
+    with(|bus| unsafe { bus.ram.as_mut_ptr().add(addr as usize) })
+}
+
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn read(addr: u16) -> u8 {
+    with(|bus| bus.read(addr))
+}
+
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn write(addr: u16, value: u8) {
+    with(|bus| bus.write(addr, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec;
+
+    use super::*;
+    use crate::addr::Addr;
+
+    const PPU_CTRL: Addr = Addr(0x2000);
+    const PPU_STATUS: Addr = Addr(0x2002);
+    const PPU_ADDR: Addr = Addr(0x2006);
+    const PPU_DATA: Addr = Addr(0x2007);
+    const JOYPAD1: Addr = Addr(0x4016);
+
+    unsafe fn set_addr(addr: u16) {
+        PPU_ADDR.write((addr >> 8) as u8);
+        PPU_ADDR.write(addr as u8);
+    }
+
+    #[test]
+    fn data_writes_increment_address() {
+        reset();
+        unsafe {
+            set_addr(0x2041);
+            PPU_DATA.write(1);
+            PPU_DATA.write(2);
+            PPU_CTRL.write(Ctrl::VRAM_INC_32.bits());
+            PPU_DATA.write(3);
+            PPU_DATA.write(4);
+        }
+        with(|bus| {
+            assert_eq!(bus.ppu.peek(0x2041), 1);
+            assert_eq!(bus.ppu.peek(0x2042), 2);
+            assert_eq!(bus.ppu.peek(0x2043), 3);
+            assert_eq!(bus.ppu.peek(0x2063), 4);
+            assert_eq!(bus.ppu.vram_addr, 0x2083);
+        });
+    }
+
+    #[test]
+    fn data_reads_are_buffered_except_palette() {
+        reset();
+        with(|bus| {
+            bus.ppu.poke(0x2000, 0x11);
+            bus.ppu.poke(0x2001, 0x22);
+            bus.ppu.poke(0x3F00, 0x0F);
+        });
+        unsafe {
+            set_addr(0x2000);
+            // the first read returns the stale buffer
+            PPU_DATA.read();
+            assert_eq!(PPU_DATA.read(), 0x11);
+            assert_eq!(PPU_DATA.read(), 0x22);
+            set_addr(0x3F00);
+            assert_eq!(PPU_DATA.read(), 0x0F);
+        }
+    }
+
+    #[test]
+    fn mirrors_resolve_to_the_same_byte() {
+        reset();
+        with(|bus| {
+            bus.ppu.mirroring = Mirroring::Horizontal;
+            bus.ppu.poke(0x2000, 7);
+            bus.ppu.poke(0x3F10, 9);
+            assert_eq!(bus.ppu.peek(0x2400), 7);
+            assert_eq!(bus.ppu.peek(0x2800), 0);
+            assert_eq!(bus.ppu.peek(0x3F00), 9);
+        });
+    }
+
+    #[test]
+    fn status_read_clears_vblank_and_write_toggle() {
+        reset();
+        vblank();
+        unsafe {
+            PPU_ADDR.write(0x21);
+            assert!(Status(PPU_STATUS.read()).contains(Status::VBLANK));
+            assert!(!Status(PPU_STATUS.read()).contains(Status::VBLANK));
+            // the toggle is back to the high byte
+            set_addr(0x2345);
+        }
+        with(|bus| {
+            assert_eq!(bus.ppu.vram_addr, 0x2345);
+            assert_eq!(bus.frame, 1);
+            assert_eq!(bus.ram[0x80], 1);
+        });
+    }
+
+    #[test]
+    fn apu_writes_are_logged_in_order() {
+        reset();
+        unsafe {
+            Addr(0x4000).write(0x3F);
+            Addr(0x4003).write(0x08);
+            Addr(0x4015).write(0x0F);
+            assert_eq!(Addr(0x4015).read(), 0x0F);
+            assert_eq!(Addr(0x4003).read(), 0);
+        }
+        with(|bus| {
+            assert_eq!(
+                bus.apu.writes,
+                vec![(0x4000, 0x3F), (0x4003, 0x08), (0x4015, 0x0F)]
+            );
+            assert_eq!(bus.apu.regs[3], 0x08);
+        });
+    }
+
+    #[test]
+    fn oam_dma_copies_a_page() {
+        reset();
+        with(|bus| bus.ram[0x200..0x300].copy_from_slice(&[0xAB; 0x100]));
+        unsafe {
+            Addr(0x4014).write(0x02);
+        }
+        with(|bus| assert_eq!(bus.ppu.oam, [0xAB; 0x100]));
+    }
+
+    #[test]
+    fn joypad_shifts_out_after_strobe() {
+        reset();
+        // A, Start, Right
+        set_buttons(0b1001_0001);
+        let read = || {
+            let mut bits = 0u8;
+            for _ in 0..8 {
+                bits = bits << 1 | unsafe { JOYPAD1.read() };
+            }
+            bits
+        };
+        unsafe {
+            JOYPAD1.write(1);
+            // while strobing, every read is A
+            assert_eq!(JOYPAD1.read(), 1);
+            assert_eq!(JOYPAD1.read(), 1);
+            JOYPAD1.write(0);
+        }
+        assert_eq!(read(), 0b1001_0001);
+        // then 1s once all 8 are out
+        assert_eq!(read(), 0xFF);
+    }
+
+    #[test]
+    #[should_panic(expected = "is a register")]
+    fn register_pointers_panic() {
+        Addr(0x2007).as_ptr();
+    }
+}
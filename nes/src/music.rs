@@ -0,0 +1,517 @@
+// Music driver for Pulse 1, Pulse 2, Triangle and Noise.
+//
+// A song has one track per channel. A track is a list of patterns played
+// in order, looping back to `loop_to` at the end, and a pattern is a list
+// of events. Notes and rests last a number of rows, and a row lasts
+// `speed` frames, so channels don't need to line up pattern by pattern.
+//     const LEAD: Instrument = Instrument::new(2, Envelope::new(&[15, 12, 10, 8], NO_LOOP));
+//     const VERSE: &Pattern = &[
+//         Event::Instrument(&LEAD),
+//         Event::Note(Note::parse("C4"), 2),
+//         Event::Note(Note::parse("E4"), 2),
+//         Event::Rest(4),
+//     ];
+//     const SONG: Song = Song {
+//         speed: 6,
+//         tracks: [Track::new(&[VERSE], Some(0)), Track::EMPTY, Track::EMPTY, Track::EMPTY],
+//     };
+//     static mut MUSIC: Music = Music::new();
+//     MUSIC.play(&SONG);
+//     // once per frame
+//     MUSIC.tick();
+//
+// Pitches follow `note::region()`. On the noise channel only the low 4
+// bits of the note number count, 15 being the highest pitch.
+//
+// Channels borrowed by a sound effect (see `apu::APU::play_sfx`) keep
+// playing silently, and the current note restarts when they come back
+// with their halt flag as it was before the effect.
+use crate::{
+    apu::{self, Channel, Pulse, Status, NOISE, PULSE_1, PULSE_2, TRIANGLE},
+    note::{self, Note},
+};
+
+pub const CHANNELS: usize = 4;
+const VOICE_CHANNELS: [Channel; CHANNELS] = [
+    Channel::Pulse1,
+    Channel::Pulse2,
+    Channel::Triangle,
+    Channel::Noise,
+];
+pub const NO_LOOP: u8 = 0xFF;
+const NO_NOTE: u8 = 0xFF;
+
+// one cycle of vibrato
+const SINE: [i8; 32] = [
+    0, 25, 49, 71, 90, 106, 117, 125, 127, 125, 117, 106, 90, 71, 49, 25, 0, -25, -49, -71, -90,
+    -106, -117, -125, -127, -125, -117, -106, -90, -71, -49, -25,
+];
+
+// Per-frame values, 0-15 for volume. After the last value the envelope
+// jumps back to `loop_at`, or holds the last value with `NO_LOOP`.
+pub struct Envelope {
+    pub values: &'static [u8],
+    pub loop_at: u8,
+}
+impl Envelope {
+    pub const fn new(values: &'static [u8], loop_at: u8) -> Self {
+        Self { values, loop_at }
+    }
+}
+
+pub struct Instrument {
+    // pulse duty 0-3; on noise, 1 selects the short (metallic) mode
+    pub duty: u8,
+    pub volume: Envelope,
+}
+impl Instrument {
+    pub const fn new(duty: u8, volume: Envelope) -> Self {
+        Self { duty, volume }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum Event {
+    // note, length in rows
+    Note(Note, u8),
+    // silence for a number of rows
+    Rest(u8),
+    // carry on with the current note (or silence) for a number of rows,
+    // e.g. across a pattern boundary or after an effect change
+    Hold(u8),
+    Instrument(&'static Instrument),
+    // channel volume 0-15, scales the instrument's envelope
+    Volume(u8),
+    // cycle the note through +0, +x and +y semitones, a step per frame;
+    // (0, 0) turns it off
+    Arpeggio(u8, u8),
+    // speed (phase steps per frame, 256 per cycle) and depth in period
+    // units; a depth of 0 turns it off
+    Vibrato(u8, u8),
+    // period change per frame, negative slides up; reset by each note
+    Slide(i8),
+    // frames per row, for the whole song
+    Speed(u8),
+}
+
+pub type Pattern = [Event];
+
+pub struct Track {
+    pub patterns: &'static [&'static Pattern],
+    // pattern to continue from after the last one, or None to stop
+    pub loop_to: Option<u8>,
+}
+impl Track {
+    pub const EMPTY: Track = Track::new(&[], None);
+
+    pub const fn new(patterns: &'static [&'static Pattern], loop_to: Option<u8>) -> Self {
+        Self { patterns, loop_to }
+    }
+}
+
+pub struct Song {
+    // frames per row
+    pub speed: u8,
+    // Pulse 1, Pulse 2, Triangle, Noise
+    pub tracks: [Track; CHANNELS],
+}
+
+#[derive(Copy, Clone)]
+struct Voice {
+    pattern: u8,
+    event: u8,
+    rows_left: u8,
+    stopped: bool,
+    note: u8,
+    // set for the first frame of a note
+    trigger: bool,
+    instrument: Option<&'static Instrument>,
+    envelope: u8,
+    volume: u8,
+    arpeggio: (u8, u8),
+    arpeggio_step: u8,
+    vibrato_speed: u8,
+    vibrato_depth: u8,
+    vibrato_phase: u8,
+    slide: i8,
+    slide_offset: i16,
+}
+
+impl Voice {
+    const fn new() -> Self {
+        Self {
+            pattern: 0,
+            event: 0,
+            rows_left: 0,
+            stopped: false,
+            note: NO_NOTE,
+            trigger: false,
+            instrument: None,
+            envelope: 0,
+            volume: 15,
+            arpeggio: (0, 0),
+            arpeggio_step: 0,
+            vibrato_speed: 0,
+            vibrato_depth: 0,
+            vibrato_phase: 0,
+            slide: 0,
+            slide_offset: 0,
+        }
+    }
+
+    // run events up to the next note or rest, returning a speed change
+    fn next_row(&mut self, track: &Track) -> Option<u8> {
+        let mut speed = None;
+        if self.stopped {
+            return speed;
+        }
+        if self.rows_left > 1 {
+            self.rows_left -= 1;
+            return speed;
+        }
+        // looping twice without finding a note means there aren't any
+        let mut looped = false;
+        loop {
+            let Some(pattern) = track.patterns.get(self.pattern as usize) else {
+                match track.loop_to {
+                    Some(to) if (to as usize) < track.patterns.len() && !looped => {
+                        looped = true;
+                        self.pattern = to;
+                        self.event = 0;
+                        continue;
+                    }
+                    _ => {
+                        self.stopped = true;
+                        self.note = NO_NOTE;
+                        return speed;
+                    }
+                }
+            };
+            let Some(&event) = pattern.get(self.event as usize) else {
+                self.pattern += 1;
+                self.event = 0;
+                continue;
+            };
+            self.event += 1;
+            match event {
+                Event::Note(note, rows) => {
+                    self.note = note.0;
+                    self.rows_left = rows;
+                    self.trigger = true;
+                    self.envelope = 0;
+                    self.arpeggio_step = 0;
+                    self.slide_offset = 0;
+                    return speed;
+                }
+                Event::Rest(rows) => {
+                    self.note = NO_NOTE;
+                    self.rows_left = rows;
+                    return speed;
+                }
+                Event::Hold(rows) => {
+                    self.rows_left = rows;
+                    return speed;
+                }
+                Event::Instrument(instrument) => self.instrument = Some(instrument),
+                Event::Volume(volume) => self.volume = volume & 0x0F,
+                Event::Arpeggio(x, y) => self.arpeggio = (x, y),
+                Event::Vibrato(rate, depth) => {
+                    self.vibrato_speed = rate;
+                    self.vibrato_depth = depth;
+                }
+                Event::Slide(slide) => self.slide = slide,
+                Event::Speed(frames) => speed = Some(frames),
+            }
+        }
+    }
+
+    // volume 0-15 for this frame, advancing the envelope
+    fn frame_volume(&mut self) -> u8 {
+        let level = match self.instrument {
+            Some(instrument) => {
+                let envelope = &instrument.volume;
+                let Some(&level) = envelope.values.get(self.envelope as usize) else {
+                    return 0;
+                };
+                if self.envelope as usize + 1 < envelope.values.len() {
+                    self.envelope += 1;
+                } else if envelope.loop_at != NO_LOOP {
+                    self.envelope = envelope.loop_at;
+                }
+                level
+            }
+            None => 15,
+        };
+        let volume = (level & 0x0F) * self.volume / 15;
+        // don't let quiet notes disappear completely
+        if volume == 0 && level != 0 && self.volume != 0 {
+            1
+        } else {
+            volume
+        }
+    }
+
+    // note for this frame including arpeggio
+    fn frame_note(&mut self) -> u8 {
+        let (x, y) = self.arpeggio;
+        if x == 0 && y == 0 {
+            return self.note;
+        }
+        let offset = match self.arpeggio_step {
+            0 => 0,
+            1 => x,
+            _ => y,
+        };
+        self.arpeggio_step = if self.arpeggio_step >= 2 {
+            0
+        } else {
+            self.arpeggio_step + 1
+        };
+        self.note.saturating_add(offset)
+    }
+
+    // slide and vibrato on top of `period`
+    fn bend(&mut self, period: u16) -> u16 {
+        self.slide_offset = self.slide_offset.saturating_add(self.slide as i16);
+        let mut period = period as i16 + self.slide_offset;
+        if self.vibrato_depth != 0 {
+            self.vibrato_phase = self.vibrato_phase.wrapping_add(self.vibrato_speed);
+            let sine = SINE[(self.vibrato_phase >> 3) as usize] as i16;
+            period += sine * self.vibrato_depth as i16 / 128;
+        }
+        period.clamp(0, 0x7FF) as u16
+    }
+
+    fn duty(&self) -> u8 {
+        self.instrument
+            .map_or(0, |instrument| instrument.duty & 0x03)
+    }
+}
+
+pub struct Music {
+    song: Option<&'static Song>,
+    voices: [Voice; CHANNELS],
+    speed: u8,
+    // frames until the next row
+    timer: u8,
+    // channels sound effects had last frame
+    borrowed: Status,
+}
+
+impl Default for Music {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Music {
+    pub const fn new() -> Self {
+        Self {
+            song: None,
+            voices: [Voice::new(); CHANNELS],
+            speed: 6,
+            timer: 0,
+            borrowed: Status(0),
+        }
+    }
+
+    pub fn play(&mut self, song: &'static Song) {
+        self.song = Some(song);
+        self.voices = [Voice::new(); CHANNELS];
+        self.speed = song.speed.max(1);
+        self.timer = 0;
+        self.borrowed = apu::borrowed();
+    }
+
+    // silences the channels not in use by sound effects
+    pub fn stop(&mut self) {
+        self.song = None;
+        let borrowed = apu::borrowed();
+        unsafe {
+            if !borrowed.contains(Status::PULSE_1) {
+                PULSE_1.silence();
+            }
+            if !borrowed.contains(Status::PULSE_2) {
+                PULSE_2.silence();
+            }
+            if !borrowed.contains(Status::TRIANGLE) {
+                TRIANGLE.silence();
+            }
+            if !borrowed.contains(Status::NOISE) {
+                NOISE.silence();
+            }
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.song.is_some()
+    }
+
+    // Advance by one frame and update the APU, before `APU::run_sfx`.
+    pub fn tick(&mut self) {
+        let Some(song) = self.song else {
+            return;
+        };
+        if self.timer == 0 {
+            for (voice, track) in self.voices.iter_mut().zip(song.tracks.iter()) {
+                if let Some(speed) = voice.next_row(track) {
+                    self.speed = speed.max(1);
+                }
+            }
+            self.timer = self.speed;
+        }
+        self.timer -= 1;
+
+        if self.voices.iter().all(|voice| voice.stopped) {
+            self.stop();
+            return;
+        }
+
+        // channels handed back since last frame restart their note, which
+        // rewrites every register the effect touched
+        let borrowed = apu::borrowed();
+        for (voice, channel) in self.voices.iter_mut().zip(VOICE_CHANNELS) {
+            if self.borrowed.contains(channel.status()) && !borrowed.contains(channel.status()) {
+                voice.trigger = true;
+            }
+        }
+        self.borrowed = borrowed;
+
+        unsafe {
+            self.write_pulse(0, &PULSE_1, borrowed.contains(Status::PULSE_1));
+            self.write_pulse(1, &PULSE_2, borrowed.contains(Status::PULSE_2));
+            self.write_triangle(borrowed.contains(Status::TRIANGLE));
+            self.write_noise(borrowed.contains(Status::NOISE));
+        }
+    }
+
+    // Each of these steps the voice's envelope and effects, then writes
+    // the registers unless the channel is `borrowed`.
+    unsafe fn write_pulse(&mut self, index: usize, pulse: &Pulse, borrowed: bool) {
+        let voice = &mut self.voices[index];
+        if voice.note == NO_NOTE {
+            if !borrowed {
+                pulse.silence();
+            }
+            return;
+        }
+        let volume = voice.frame_volume();
+        let note = voice.frame_note();
+        let period = voice.bend(Note(note).pulse_period(note::region()));
+        if borrowed {
+            return;
+        }
+        if voice.trigger {
+            pulse.trigger(period, 0);
+        } else {
+            pulse.set_period(period);
+        }
+        pulse.set_volume(voice.duty(), volume);
+        voice.trigger = false;
+    }
+
+    unsafe fn write_triangle(&mut self, borrowed: bool) {
+        let voice = &mut self.voices[2];
+        if voice.note == NO_NOTE || voice.frame_volume() == 0 {
+            if !borrowed {
+                TRIANGLE.silence();
+            }
+            return;
+        }
+        let note = voice.frame_note();
+        let period = voice.bend(Note(note).triangle_period(note::region()));
+        if borrowed {
+            return;
+        }
+        if voice.trigger {
+            TRIANGLE.trigger(period, 0);
+        } else {
+            TRIANGLE.set_period(period);
+        }
+        TRIANGLE.on();
+        voice.trigger = false;
+    }
+
+    unsafe fn write_noise(&mut self, borrowed: bool) {
+        let voice = &mut self.voices[3];
+        if voice.note == NO_NOTE {
+            if !borrowed {
+                NOISE.silence();
+            }
+            return;
+        }
+        let volume = voice.frame_volume();
+        let pitch = voice.frame_note() & 0x0F;
+        if borrowed {
+            return;
+        }
+        NOISE.set_period(voice.duty() & 1 != 0, 0x0F - pitch);
+        if voice.trigger {
+            NOISE.trigger(0);
+            voice.trigger = false;
+        }
+        NOISE.set_volume(volume);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        apu::{SfxStep, SoundEffect, APU},
+        mock_bus,
+        note::Region,
+        synth::Synth,
+    };
+
+    const HELD: &Pattern = &[Event::Note(Note::A4, 255)];
+    const SONG: Song = Song {
+        speed: 6,
+        tracks: [
+            Track::new(&[HELD], None),
+            Track::EMPTY,
+            Track::EMPTY,
+            Track::EMPTY,
+        ],
+    };
+    // halt clear, so its notes end by themselves
+    const BLIP: SoundEffect =
+        SoundEffect::new(Channel::Pulse1, &[SfxStep::new(4, 0x100, 0b1001_1111)]);
+
+    fn loudness(samples: &[i16]) -> i32 {
+        let max = samples.iter().max().copied().unwrap_or(0) as i32;
+        let min = samples.iter().min().copied().unwrap_or(0) as i32;
+        max - min
+    }
+
+    #[test]
+    fn music_gets_its_halt_flag_back_after_an_effect() {
+        mock_bus::reset();
+        note::set_region(Region::Ntsc);
+        apu::init();
+        let mut synth = Synth::new(Region::Ntsc);
+        let mut music = Music::new();
+        let mut apu = APU::default();
+        music.play(&SONG);
+        let mut frame = |music: &mut Music, apu: &mut APU| {
+            music.tick();
+            apu.run_sfx();
+            synth.run_frame();
+            let samples = synth.samples();
+            loudness(&samples[samples.len() - 700..])
+        };
+        for _ in 0..10 {
+            frame(&mut music, &mut apu);
+        }
+        assert!(apu.play_sfx(&BLIP));
+        for _ in 0..10 {
+            frame(&mut music, &mut apu);
+        }
+        assert!(Channel::Pulse1.is_halted());
+        // well past the 10 half frames of length 0
+        for _ in 0..30 {
+            frame(&mut music, &mut apu);
+        }
+        assert!(frame(&mut music, &mut apu) > 1000);
+    }
+}
@@ -0,0 +1,781 @@
+// Converts FamiTracker text exports (File > Export text; FamiStudio can
+// write the same format) into Rust source for `nes::music`. Host only, so
+// it's meant to be called from a game's build script:
+//     // Cargo.toml: [build-dependencies] nes = { path = "../nes" }
+//     let text = fs::read_to_string("music/theme.txt").unwrap();
+//     let rust = nes::music_import::convert(&text, "THEME").unwrap_or_else(|e| panic!("theme.txt: {e}"));
+//     fs::write(Path::new(&env::var("OUT_DIR").unwrap()).join("theme.rs"), rust).unwrap();
+//     // in the game
+//     include!(concat!(env!("OUT_DIR"), "/theme.rs"));
+//     MUSIC.play(&THEME_SONG_0);
+//
+// Anything the driver can't play is an error rather than being dropped:
+// expansion chips, DPCM, arpeggio/pitch/duty macros, release points,
+// tempo other than 150, and effects other than 0xy, 1xx, 2xx, 4xy, Bxx,
+// Cxx and Fxx (speed only). Vibrato depth is approximated.
+extern crate std;
+
+use std::{
+    collections::BTreeMap,
+    fmt, format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+// Pulse 1, Pulse 2, Triangle, Noise, DPCM
+const COLUMNS: usize = 5;
+
+// FamiTracker's vibrato depths, in period units
+const VIBRATO_DEPTH: [u8; 16] = [1, 1, 2, 3, 4, 5, 7, 10, 12, 14, 17, 22, 30, 44, 64, 96];
+
+#[derive(Debug)]
+pub struct Error {
+    // 1-based line in the export, 0 if it isn't about one line
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+}
+
+fn error<T>(line: usize, message: impl Into<String>) -> Result<T, Error> {
+    Err(Error {
+        line,
+        message: message.into(),
+    })
+}
+
+struct Macro {
+    loop_at: i32,
+    release: i32,
+    values: Vec<i32>,
+}
+
+struct Instrument {
+    line: usize,
+    volume: i32,
+    arpeggio: i32,
+    pitch: i32,
+    hi_pitch: i32,
+    duty: i32,
+}
+
+#[derive(Clone, Default)]
+struct Cell {
+    // "C-4", "---", ... ; empty for "..."
+    note: String,
+    instrument: Option<u8>,
+    volume: Option<u8>,
+    effects: Vec<String>,
+}
+
+#[derive(Default)]
+struct Song {
+    line: usize,
+    rows: usize,
+    speed: u8,
+    // per frame, the pattern for each column
+    order: Vec<[u8; COLUMNS]>,
+    // (pattern, row) -> cells
+    patterns: BTreeMap<u8, BTreeMap<usize, Vec<(usize, Cell)>>>,
+}
+
+#[derive(Default)]
+struct Module {
+    macros: BTreeMap<(i32, i32), Macro>,
+    instruments: BTreeMap<u8, Instrument>,
+    songs: Vec<Song>,
+}
+
+fn parse_int(line: usize, token: &str) -> Result<i32, Error> {
+    token
+        .parse()
+        .or_else(|_| error(line, format!("expected a number, found `{token}`")))
+}
+
+fn parse_hex(line: usize, token: &str) -> Result<u8, Error> {
+    u8::from_str_radix(token, 16).or_else(|_| error(line, format!("expected hex, found `{token}`")))
+}
+
+fn parse(text: &str) -> Result<Module, Error> {
+    let mut module = Module::default();
+    let mut pattern = None;
+    for (i, raw) in text.lines().enumerate() {
+        let line = i + 1;
+        let raw = raw.trim();
+        if raw.is_empty() || raw.starts_with('#') {
+            continue;
+        }
+        let (head, rest) = raw.split_once(char::is_whitespace).unwrap_or((raw, ""));
+        let rest = rest.trim();
+        let words: Vec<&str> = rest.split_whitespace().collect();
+        match head {
+            "EXPANSION" if parse_int(line, words.first().unwrap_or(&""))? != 0 => {
+                return error(line, "expansion chips aren't supported, only the 2A03");
+            }
+            "MACRO" => {
+                let (params, values) = rest.split_once(':').unwrap_or((rest, ""));
+                let params: Vec<&str> = params.split_whitespace().collect();
+                if params.len() < 4 {
+                    return error(line, "MACRO needs type, index, loop and release");
+                }
+                let mut nums = vec![];
+                for token in values.split_whitespace() {
+                    if token != "|" {
+                        nums.push(parse_int(line, token)?);
+                    }
+                }
+                let key = (parse_int(line, params[0])?, parse_int(line, params[1])?);
+                module.macros.insert(
+                    key,
+                    Macro {
+                        loop_at: parse_int(line, params[2])?,
+                        release: parse_int(line, params[3])?,
+                        values: nums,
+                    },
+                );
+            }
+            "INST2A03" => {
+                if words.len() < 6 {
+                    return error(line, "INST2A03 needs an index and five macro indices");
+                }
+                let index = parse_int(line, words[0])?;
+                module.instruments.insert(
+                    index as u8,
+                    Instrument {
+                        line,
+                        volume: parse_int(line, words[1])?,
+                        arpeggio: parse_int(line, words[2])?,
+                        pitch: parse_int(line, words[3])?,
+                        hi_pitch: parse_int(line, words[4])?,
+                        duty: parse_int(line, words[5])?,
+                    },
+                );
+            }
+            "INSTVRC6" | "INSTVRC7" | "INSTFDS" | "INSTN163" | "INSTS5B" => {
+                return error(line, "expansion chip instruments aren't supported");
+            }
+            "DPCMDEF" | "KEYDPCM" => {
+                return error(line, "DPCM samples aren't supported by the music driver");
+            }
+            "TRACK" => {
+                if words.len() < 3 {
+                    return error(line, "TRACK needs rows, speed and tempo");
+                }
+                let tempo = parse_int(line, words[2])?;
+                if tempo != 150 {
+                    return error(
+                        line,
+                        format!("tempo {tempo} isn't supported, only 150 (set the speed instead)"),
+                    );
+                }
+                module.songs.push(Song {
+                    line,
+                    rows: parse_int(line, words[0])? as usize,
+                    speed: parse_int(line, words[1])? as u8,
+                    ..Song::default()
+                });
+                pattern = None;
+            }
+            "ORDER" => {
+                let Some(song) = module.songs.last_mut() else {
+                    return error(line, "ORDER before TRACK");
+                };
+                let (_, patterns) = rest.split_once(':').unwrap_or((rest, ""));
+                let mut frame = [0; COLUMNS];
+                let patterns: Vec<&str> = patterns.split_whitespace().collect();
+                if patterns.len() != COLUMNS {
+                    return error(line, format!("expected {COLUMNS} channels"));
+                }
+                for (slot, token) in frame.iter_mut().zip(patterns) {
+                    *slot = parse_hex(line, token)?;
+                }
+                song.order.push(frame);
+            }
+            "PATTERN" => {
+                let Some(song) = module.songs.last_mut() else {
+                    return error(line, "PATTERN before TRACK");
+                };
+                let index = parse_hex(line, words.first().unwrap_or(&""))?;
+                song.patterns.entry(index).or_default();
+                pattern = Some(index);
+            }
+            "ROW" => {
+                let (Some(song), Some(index)) = (module.songs.last_mut(), pattern) else {
+                    return error(line, "ROW outside a PATTERN");
+                };
+                let mut columns = rest.split(':');
+                let row = parse_hex(line, columns.next().unwrap_or("").trim())? as usize;
+                let mut cells = vec![];
+                for column in columns {
+                    let tokens: Vec<&str> = column.split_whitespace().collect();
+                    if tokens.len() < 3 {
+                        return error(line, "expected note, instrument and volume in each channel");
+                    }
+                    let cell = Cell {
+                        note: if tokens[0] == "..." {
+                            String::new()
+                        } else {
+                            tokens[0].to_string()
+                        },
+                        instrument: match tokens[1] {
+                            ".." => None,
+                            token => Some(parse_hex(line, token)?),
+                        },
+                        volume: match tokens[2] {
+                            "." => None,
+                            token => Some(parse_hex(line, token)?),
+                        },
+                        effects: tokens[3..]
+                            .iter()
+                            .filter(|effect| **effect != "...")
+                            .map(|effect| effect.to_string())
+                            .collect(),
+                    };
+                    cells.push((line, cell));
+                }
+                if cells.len() != COLUMNS {
+                    return error(line, format!("expected {COLUMNS} channels"));
+                }
+                song.patterns.entry(index).or_default().insert(row, cells);
+            }
+            _ => {}
+        }
+    }
+    Ok(module)
+}
+
+fn note_number(line: usize, channel: usize, note: &str) -> Result<u8, Error> {
+    let bytes = note.as_bytes();
+    if bytes.len() != 3 {
+        return error(line, format!("can't read note `{note}`"));
+    }
+    if channel == 3 {
+        // noise notes are "0-#" to "F-#"
+        return parse_hex(line, &note[..1]);
+    }
+    let semitone = match &note[..2] {
+        "C-" => 0,
+        "C#" => 1,
+        "D-" => 2,
+        "D#" => 3,
+        "E-" => 4,
+        "F-" => 5,
+        "F#" => 6,
+        "G-" => 7,
+        "G#" => 8,
+        "A-" => 9,
+        "A#" => 10,
+        "B-" => 11,
+        _ => return error(line, format!("can't read note `{note}`")),
+    };
+    let octave = (bytes[2] as char)
+        .to_digit(10)
+        .map_or_else(|| error(line, format!("can't read note `{note}`")), Ok)?;
+    Ok(octave as u8 * 12 + semitone)
+}
+
+// the macro an instrument points to, if any
+fn macro_values(module: &Module, kind: i32, index: i32) -> Option<&Macro> {
+    if index < 0 {
+        None
+    } else {
+        module.macros.get(&(kind, index))
+    }
+}
+
+fn instrument_source(
+    module: &Module,
+    prefix: &str,
+    index: u8,
+    inst: &Instrument,
+) -> Result<String, Error> {
+    let line = inst.line;
+    for (kind, name, which) in [
+        (1, "arpeggio", inst.arpeggio),
+        (2, "pitch", inst.pitch),
+        (3, "hi-pitch", inst.hi_pitch),
+    ] {
+        if macro_values(module, kind, which).is_some() {
+            return error(
+                line,
+                format!(
+                    "instrument {index:02X}: {name} macros aren't supported, use effects instead"
+                ),
+            );
+        }
+    }
+    let duty = match macro_values(module, 4, inst.duty) {
+        None => 0,
+        Some(duty) => {
+            let first = duty.values.first().copied().unwrap_or(0);
+            if duty.values.iter().any(|value| *value != first) {
+                return error(
+                    line,
+                    format!("instrument {index:02X}: duty macros must hold one value"),
+                );
+            }
+            first
+        }
+    };
+    let (values, loop_at) = match macro_values(module, 0, inst.volume) {
+        None => (vec![15], -1),
+        Some(volume) => {
+            if volume.release >= 0 {
+                return error(
+                    line,
+                    format!("instrument {index:02X}: release points aren't supported"),
+                );
+            }
+            if volume.values.is_empty() || volume.values.iter().any(|v| !(0..=15).contains(v)) {
+                return error(
+                    line,
+                    format!("instrument {index:02X}: volume macro must be 1+ values 0-15"),
+                );
+            }
+            (volume.values.clone(), volume.loop_at)
+        }
+    };
+    let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+    let loop_at = if loop_at < 0 {
+        "nes::music::NO_LOOP".to_string()
+    } else {
+        loop_at.to_string()
+    };
+    Ok(format!(
+        "pub const {prefix}_INSTRUMENT_{index:02X}: nes::music::Instrument = nes::music::Instrument::new(\n    {duty},\n    nes::music::Envelope::new(&[{}], {loop_at}),\n);\n",
+        values.join(", ")
+    ))
+}
+
+// what a song's order does at the end
+enum Ending {
+    Loop(usize),
+    Stop,
+}
+
+// events for one cell except the note
+fn cell_events(
+    line: usize,
+    prefix: &str,
+    cell: &Cell,
+    module: &Module,
+    events: &mut Vec<String>,
+    ending: &mut Option<Ending>,
+) -> Result<(), Error> {
+    if let Some(instrument) = cell.instrument {
+        if !module.instruments.contains_key(&instrument) {
+            return error(line, format!("instrument {instrument:02X} isn't defined"));
+        }
+        events.push(format!("Instrument(&{prefix}_INSTRUMENT_{instrument:02X})"));
+    }
+    if let Some(volume) = cell.volume {
+        events.push(format!("Volume({volume})"));
+    }
+    for effect in &cell.effects {
+        let (kind, param) = effect.split_at(1);
+        let value = parse_hex(line, param)?;
+        let (x, y) = (value >> 4, value & 0x0F);
+        match kind {
+            "0" => events.push(format!("Arpeggio({x}, {y})")),
+            "1" | "2" => {
+                if value > 0x7F {
+                    return error(
+                        line,
+                        format!("slide speed in `{effect}` is too fast, 7F at most"),
+                    );
+                }
+                let slide = if kind == "1" {
+                    -(value as i16)
+                } else {
+                    value as i16
+                };
+                events.push(format!("Slide({slide})"));
+            }
+            "4" => {
+                let depth = if y == 0 { 0 } else { VIBRATO_DEPTH[y as usize] };
+                events.push(format!("Vibrato({}, {depth})", x * 4));
+            }
+            "F" => {
+                if value == 0 || value >= 0x20 {
+                    return error(
+                        line,
+                        format!("`{effect}` sets the tempo; only speeds 01-1F are supported"),
+                    );
+                }
+                events.push(format!("Speed({value})"));
+            }
+            "B" => *ending = Some(Ending::Loop(value as usize)),
+            "C" => *ending = Some(Ending::Stop),
+            _ => return error(line, format!("effect `{effect}` isn't supported")),
+        }
+    }
+    Ok(())
+}
+
+// how the rows since the last event sound
+#[derive(Copy, Clone)]
+enum Span {
+    Hold,
+    Rest,
+    Note(u8),
+}
+
+fn span_events(span: Span, mut rows: usize, events: &mut Vec<String>) {
+    let mut span = span;
+    while rows > 0 {
+        let len = rows.min(255);
+        events.push(match span {
+            Span::Hold => format!("Hold({len})"),
+            Span::Rest => format!("Rest({len})"),
+            Span::Note(note) => format!("Note(nes::note::Note({note}), {len})"),
+        });
+        // anything longer than a u8 carries on as it is
+        span = Span::Hold;
+        rows -= len;
+    }
+}
+
+fn song_source(module: &Module, prefix: &str, number: usize, song: &Song) -> Result<String, Error> {
+    if song.order.is_empty() {
+        return error(song.line, "song has no frames");
+    }
+    let name = format!("{prefix}_SONG_{number}");
+    let mut out = String::new();
+    let mut ending = None;
+    // the order is cut short by the first Bxx or Cxx, and so is its frame,
+    // after the effect's row
+    let mut frames = song.order.len();
+    let mut last_rows = song.rows;
+    for (frame, order) in song.order.iter().enumerate() {
+        let mut frame_ending = None;
+        for (channel, pattern) in order.iter().enumerate() {
+            let rows = song.patterns.get(pattern);
+            for (&row, cells) in rows.iter().flat_map(|rows| rows.iter()) {
+                let (line, cell) = &cells[channel];
+                if channel == 4 && !cell.note.is_empty() {
+                    return error(*line, "DPCM notes aren't supported by the music driver");
+                }
+                let mut cell_ending = None;
+                cell_events(*line, prefix, cell, module, &mut vec![], &mut cell_ending)?;
+                if cell_ending.is_some() && (frame_ending.is_none() || row + 1 < last_rows) {
+                    frame_ending = cell_ending;
+                    last_rows = row + 1;
+                }
+            }
+        }
+        if frame_ending.is_some() {
+            ending = frame_ending;
+            frames = frame + 1;
+            break;
+        }
+    }
+    let loop_to = match ending {
+        None => Some(0),
+        Some(Ending::Loop(to)) if to < frames => Some(to),
+        Some(Ending::Loop(to)) => {
+            return error(
+                song.line,
+                format!("loop to frame {to:02X}, which doesn't exist"),
+            )
+        }
+        Some(Ending::Stop) => None,
+    };
+
+    let mut tracks = vec![];
+    for channel in 0..4 {
+        let mut patterns: Vec<String> = vec![];
+        for (frame, order) in song.order[..frames].iter().enumerate() {
+            let pattern = order[channel];
+            let rows_played = if frame + 1 == frames {
+                last_rows.min(song.rows)
+            } else {
+                song.rows
+            };
+            let pattern_name = if rows_played < song.rows {
+                format!("{name}_{channel}_{pattern:02X}_END")
+            } else {
+                format!("{name}_{channel}_{pattern:02X}")
+            };
+            if patterns.contains(&pattern_name) {
+                patterns.push(pattern_name);
+                continue;
+            }
+            let empty = BTreeMap::new();
+            let rows = song.patterns.get(&pattern).unwrap_or(&empty);
+            let mut events = vec![];
+            let (mut span, mut span_rows) = (Span::Hold, 0);
+            for row in 0..rows_played {
+                let Some((line, cell)) = rows.get(&row).map(|cells| &cells[channel]) else {
+                    span_rows += 1;
+                    continue;
+                };
+                let mut cell_extra = vec![];
+                cell_events(*line, prefix, cell, module, &mut cell_extra, &mut None)?;
+                if cell.note.is_empty() && cell_extra.is_empty() {
+                    span_rows += 1;
+                    continue;
+                }
+                span_events(span, span_rows, &mut events);
+                events.extend(cell_extra);
+                span = match cell.note.as_str() {
+                    "" => Span::Hold,
+                    "---" => Span::Rest,
+                    "===" => return error(*line, "note release (===) isn't supported"),
+                    note => Span::Note(note_number(*line, channel, note)?),
+                };
+                span_rows = 1;
+            }
+            span_events(span, span_rows, &mut events);
+            let events: Vec<String> = events
+                .iter()
+                .map(|event| format!("    nes::music::Event::{event},\n"))
+                .collect();
+            out += &format!(
+                "const {pattern_name}: &nes::music::Pattern = &[\n{}];\n",
+                events.concat()
+            );
+            patterns.push(pattern_name);
+        }
+        let loop_to = loop_to.map_or("None".to_string(), |to| format!("Some({to})"));
+        tracks.push(format!(
+            "        nes::music::Track::new(&[{}], {loop_to}),\n",
+            patterns.join(", ")
+        ));
+    }
+    out += &format!(
+        "pub const {name}: nes::music::Song = nes::music::Song {{\n    speed: {},\n    tracks: [\n{}    ],\n}};\n",
+        song.speed,
+        tracks.concat()
+    );
+    Ok(out)
+}
+
+// Rust source with `{prefix}_INSTRUMENT_xx` for each instrument and
+// `{prefix}_SONG_n` for each song (track) in the export
+pub fn convert(text: &str, prefix: &str) -> Result<String, Error> {
+    let module = parse(text)?;
+    if module.songs.is_empty() {
+        return error(0, "no TRACK found; is this a FamiTracker text export?");
+    }
+    let mut out = String::from("// generated by nes::music_import, don't edit\n");
+    for (index, inst) in &module.instruments {
+        out += &instrument_source(&module, prefix, *index, inst)?;
+    }
+    for (number, song) in module.songs.iter().enumerate() {
+        out += &song_source(&module, prefix, number, song)?;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY: &str = "... .. . ...";
+
+    // A one-song export with `header` before it. Frame n of the order
+    // plays pattern n on every channel, and `cells` lists its (row,
+    // channel, cell) entries.
+    fn export(header: &str, rows: usize, frames: &[&[(usize, usize, &str)]]) -> String {
+        let mut text = format!("{header}\nTRACK {rows} 6 150 \"Test\"\nCOLUMNS : 1 1 1 1 1\n");
+        for frame in 0..frames.len() {
+            text += &format!(
+                "ORDER {frame:02X} : {frame:02X} {frame:02X} {frame:02X} {frame:02X} {frame:02X}\n"
+            );
+        }
+        for (frame, cells) in frames.iter().enumerate() {
+            text += &format!("PATTERN {frame:02X}\n");
+            for row in 0..rows {
+                let mut columns = [EMPTY; COLUMNS];
+                for &(_, channel, cell) in cells.iter().filter(|cell| cell.0 == row) {
+                    columns[channel] = cell;
+                }
+                text += &format!("ROW {row:02X} : {}\n", columns.join(" : "));
+            }
+        }
+        text
+    }
+
+    fn convert_ok(text: &str) -> String {
+        convert(text, "T").unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn convert_err(text: &str) -> String {
+        match convert(text, "T") {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn notes_rests_and_holds() {
+        let out = convert_ok(&export(
+            "",
+            8,
+            &[&[
+                (0, 0, "C-4 .. . ..."),
+                (3, 0, "--- .. . ..."),
+                (0, 2, "A#2 .. . ..."),
+            ]],
+        ));
+        assert!(out.contains(
+            "const T_SONG_0_0_00: &nes::music::Pattern = &[\n    nes::music::Event::Note(nes::note::Note(48), 3),\n    nes::music::Event::Rest(5),\n];"
+        ));
+        assert!(out.contains("Note(nes::note::Note(34), 8)"));
+        // nothing on pulse 2 at all
+        assert!(out.contains(
+            "const T_SONG_0_1_00: &nes::music::Pattern = &[\n    nes::music::Event::Hold(8),\n];"
+        ));
+        assert!(out.contains("speed: 6,"));
+        assert!(out.contains("nes::music::Track::new(&[T_SONG_0_0_00], Some(0))"));
+    }
+
+    #[test]
+    fn noise_notes_are_hex_pitches() {
+        let out = convert_ok(&export(
+            "",
+            4,
+            &[&[(0, 3, "C-# .. . ..."), (2, 3, "3-# .. . ...")]],
+        ));
+        assert!(out.contains("Note(nes::note::Note(12), 2)"));
+        assert!(out.contains("Note(nes::note::Note(3), 2)"));
+    }
+
+    #[test]
+    fn instruments_and_macros() {
+        let header = "MACRO 0 0 1 -1 0 : 15 12 10\nMACRO 4 0 -1 -1 0 : 2 2\n\
+                      INST2A03 0 0 -1 -1 -1 0 \"Lead\"\nINST2A03 1 -1 -1 -1 -1 -1 \"Plain\"";
+        let out = convert_ok(&export(
+            header,
+            2,
+            &[&[(0, 0, "C-4 00 8 ..."), (1, 0, "D-4 01 . ...")]],
+        ));
+        assert!(out.contains(
+            "pub const T_INSTRUMENT_00: nes::music::Instrument = nes::music::Instrument::new(\n    2,\n    nes::music::Envelope::new(&[15, 12, 10], 1),\n);"
+        ));
+        assert!(out.contains("Instrument::new(\n    0,\n    nes::music::Envelope::new(&[15], nes::music::NO_LOOP),\n);"));
+        assert!(out.contains("Event::Instrument(&T_INSTRUMENT_00),\n    nes::music::Event::Volume(8),\n    nes::music::Event::Note(nes::note::Note(48), 1),"));
+        assert!(out.contains("Event::Instrument(&T_INSTRUMENT_01),\n    nes::music::Event::Note(nes::note::Note(50), 1),"));
+    }
+
+    #[test]
+    fn effects() {
+        let out = convert_ok(&export(
+            "",
+            4,
+            &[&[
+                (0, 0, "C-4 .. . 047"),
+                (1, 0, "... .. . 103"),
+                (2, 0, "... .. . 205"),
+                (3, 0, "... .. . 4A3"),
+                (3, 1, "... .. . F04"),
+            ]],
+        ));
+        for event in [
+            "Arpeggio(4, 7)",
+            "Slide(-3)",
+            "Slide(5)",
+            "Vibrato(40, 3)",
+            "Speed(4)",
+        ] {
+            assert!(out.contains(&format!("Event::{event},")), "{event}");
+        }
+        // effects without a note carry the note on
+        assert!(out.contains("Note(nes::note::Note(48), 1),\n    nes::music::Event::Slide(-3),\n    nes::music::Event::Hold(1),"));
+    }
+
+    #[test]
+    fn bxx_loops_from_its_row() {
+        let out = convert_ok(&export(
+            "",
+            8,
+            &[
+                &[(0, 0, "C-4 .. . ...")],
+                &[(0, 0, "E-4 .. . ..."), (2, 1, "... .. . B01")],
+            ],
+        ));
+        // frame 1 stops after row 2, on every channel
+        assert!(out.contains("const T_SONG_0_0_01_END: &nes::music::Pattern = &[\n    nes::music::Event::Note(nes::note::Note(52), 3),\n];"));
+        assert!(out.contains("const T_SONG_0_2_01_END: &nes::music::Pattern = &[\n    nes::music::Event::Hold(3),\n];"));
+        assert!(out.contains("Track::new(&[T_SONG_0_0_00, T_SONG_0_0_01_END], Some(1))"));
+    }
+
+    #[test]
+    fn cxx_stops_at_its_row_and_drops_later_frames() {
+        let out = convert_ok(&export(
+            "",
+            8,
+            &[
+                &[
+                    (0, 0, "C-4 .. . ..."),
+                    (5, 3, "... .. . C00"),
+                    (6, 0, "D-4 .. . C00"),
+                ],
+                &[(0, 0, "E-4 .. . ...")],
+            ],
+        ));
+        assert!(out.contains("Note(nes::note::Note(48), 6),\n];"));
+        assert!(!out.contains("Note(50)") && !out.contains("Note(52)"));
+        assert!(out.contains("Track::new(&[T_SONG_0_0_00_END], None)"));
+    }
+
+    #[test]
+    fn unsupported_features_are_errors() {
+        let note = [(0, 0, "C-4 .. . ...")];
+        let cases: [(String, &str); 11] = [
+            (export("EXPANSION 1", 1, &[&note]), "expansion chips"),
+            (
+                export("INSTVRC6 0 -1 -1 -1 -1 -1 \"X\"", 1, &[&note]),
+                "expansion chip instruments",
+            ),
+            (export("DPCMDEF 0 10 \"kick\"", 1, &[&note]), "DPCM samples"),
+            (export("", 1, &[&[(0, 4, "C-3 .. . ...")]]), "DPCM notes"),
+            (
+                export(
+                    "MACRO 1 0 -1 -1 0 : 0 4 7\nINST2A03 0 -1 0 -1 -1 -1 \"A\"",
+                    1,
+                    &[&note],
+                ),
+                "arpeggio macros",
+            ),
+            (
+                export(
+                    "MACRO 0 0 -1 1 0 : 15 8\nINST2A03 0 0 -1 -1 -1 -1 \"R\"",
+                    1,
+                    &[&note],
+                ),
+                "release points",
+            ),
+            (export("", 1, &[&[(0, 0, "C-4 .. . A01")]]), "effect `A01`"),
+            (
+                export("", 1, &[&[(0, 0, "C-4 .. . F20")]]),
+                "sets the tempo",
+            ),
+            (export("", 1, &[&[(0, 0, "C-4 .. . 180")]]), "too fast"),
+            (export("", 1, &[&[(0, 0, "=== .. . ...")]]), "note release"),
+            (
+                export("", 1, &[&[(0, 0, "C-4 05 . ...")]]),
+                "instrument 05 isn't defined",
+            ),
+        ];
+        for (text, message) in cases {
+            let error = convert_err(&text);
+            assert!(
+                error.contains(message),
+                "`{error}` should mention {message}"
+            );
+        }
+        let error = convert_err(&export("", 1, &[&note]).replace(" 150 ", " 120 "));
+        assert!(error.contains("tempo 120"), "{error}");
+        assert!(convert_err("# not an export").contains("no TRACK"));
+    }
+}
@@ -0,0 +1,279 @@
+// Musical notes C0-B7 and their timer periods, worked out at compile time
+// for NTSC and PAL CPU clocks so the same data plays in tune on both:
+//     const JINGLE: [Note; 3] = [Note::parse("C4"), Note::parse("E4"), Note::parse("G#4")];
+//     note::set_region(Region::Pal);
+//     PULSE_1.set_period(JINGLE[0].transpose(12).pulse_period(note::region()));
+//
+// The triangle plays an octave lower than a pulse at the same period, so
+// it has its own tables. Notes below A1 are out of the pulse channels'
+// range and get the longest period there is.
+
+use crate::bus::shadow;
+
+pub const NOTES: usize = 96;
+const MAX_PERIOD: u16 = 0x7FF;
+
+#[derive(Copy, Clone, PartialEq, Eq, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+}
+impl Region {
+    // CPU clock in Hz
+    pub const fn clock(self) -> u32 {
+        match self {
+            Region::Ntsc => 1_789_773,
+            Region::Pal => 1_662_607,
+        }
+    }
+}
+
+shadow!(static REGION: Region = Region::Ntsc);
+
+// the region `nes::music` and sound effect notes are tuned for
+pub fn region() -> Region {
+    REGION.get()
+}
+
+pub fn set_region(region: Region) {
+    REGION.set(region);
+}
+
+// Periods for C0..B7 on a channel that divides the CPU clock by `divider`
+// per step of its waveform (16 for pulse, 32 for triangle).
+const fn periods(region: Region, divider: u32) -> [u16; NOTES] {
+    // 2^(1/12)
+    const SEMITONE: f64 = 1.059_463_094_359_295_3;
+    // C0, 57 semitones below A4 (440 Hz)
+    let mut frequency = 16.351_597_831_287_414;
+    let mut table = [0; NOTES];
+    let mut note = 0;
+    while note < NOTES {
+        let period = region.clock() as f64 / (divider as f64 * frequency) - 1.0;
+        table[note] = if period >= MAX_PERIOD as f64 {
+            MAX_PERIOD
+        } else {
+            (period + 0.5) as u16
+        };
+        frequency *= SEMITONE;
+        note += 1;
+    }
+    table
+}
+
+pub const NTSC_PULSE: [u16; NOTES] = periods(Region::Ntsc, 16);
+pub const NTSC_TRIANGLE: [u16; NOTES] = periods(Region::Ntsc, 32);
+pub const PAL_PULSE: [u16; NOTES] = periods(Region::Pal, 16);
+pub const PAL_TRIANGLE: [u16; NOTES] = periods(Region::Pal, 32);
+
+// semitones up from C0, so A4 is 57
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Note(pub u8);
+
+impl Note {
+    pub const C0: Note = Note(0);
+    pub const A4: Note = Note(57);
+    pub const B7: Note = Note(NOTES as u8 - 1);
+
+    // `semitone` 0-11 from C
+    pub const fn new(semitone: u8, octave: u8) -> Self {
+        Self::clamped(octave as i16 * 12 + semitone as i16)
+    }
+
+    // "C4", "F#2", "Bb5", or FamiTracker's "C-4". Meant for constants, as
+    // a bad name panics.
+    pub const fn parse(name: &str) -> Self {
+        let bytes = name.as_bytes();
+        if bytes.len() < 2 || bytes.len() > 3 {
+            panic!("note: expected a name like C4, F#2 or Bb5");
+        }
+        let semitone: i16 = match bytes[0] {
+            b'C' => 0,
+            b'D' => 2,
+            b'E' => 4,
+            b'F' => 5,
+            b'G' => 7,
+            b'A' => 9,
+            b'B' => 11,
+            _ => panic!("note: letter must be A-G"),
+        };
+        let accidental = match bytes.len() {
+            3 => match bytes[1] {
+                b'#' => 1,
+                b'b' => -1,
+                b'-' => 0,
+                _ => panic!("note: accidental must be #, b or -"),
+            },
+            _ => 0,
+        };
+        let octave = bytes[bytes.len() - 1];
+        if octave < b'0' || octave > b'7' {
+            panic!("note: octave must be 0-7");
+        }
+        let note = (octave - b'0') as i16 * 12 + semitone + accidental;
+        if note < 0 || note >= NOTES as i16 {
+            panic!("note: out of range C0-B7");
+        }
+        Note(note as u8)
+    }
+
+    const fn clamped(note: i16) -> Self {
+        if note < 0 {
+            Note(0)
+        } else if note >= NOTES as i16 {
+            Self::B7
+        } else {
+            Note(note as u8)
+        }
+    }
+
+    pub const fn octave(self) -> u8 {
+        self.0 / 12
+    }
+
+    // 0-11, 0 being C
+    pub const fn semitone(self) -> u8 {
+        self.0 % 12
+    }
+
+    // up or down by `semitones`, stopping at C0 and B7
+    pub const fn transpose(self, semitones: i8) -> Self {
+        Self::clamped(self.0 as i16 + semitones as i16)
+    }
+
+    pub const fn pulse_period(self, region: Region) -> u16 {
+        let table = match region {
+            Region::Ntsc => &NTSC_PULSE,
+            Region::Pal => &PAL_PULSE,
+        };
+        table[self.index()]
+    }
+
+    pub const fn triangle_period(self, region: Region) -> u16 {
+        let table = match region {
+            Region::Ntsc => &NTSC_TRIANGLE,
+            Region::Pal => &PAL_TRIANGLE,
+        };
+        table[self.index()]
+    }
+
+    // `Note` is public, so a hand-made one can be out of range
+    const fn index(self) -> usize {
+        if (self.0 as usize) < NOTES {
+            self.0 as usize
+        } else {
+            NOTES - 1
+        }
+    }
+}
+
+// Shift `period` by roughly `cents` (hundredths of a semitone), positive
+// being sharper. Linear, so it drifts a little past +-50 cents; use
+// `Note::transpose` for whole semitones.
+pub const fn detune(period: u16, cents: i8) -> u16 {
+    // 1200 / ln 2: how many cents a 1/period change is worth
+    let shift = period as i32 * cents as i32 / 1731;
+    let period = period as i32 - shift;
+    if period < 0 {
+        0
+    } else if period > MAX_PERIOD as i32 {
+        MAX_PERIOD
+    } else {
+        period as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_names() {
+        assert_eq!(Note::parse("C0"), Note::C0);
+        assert_eq!(Note::parse("A4"), Note::A4);
+        assert_eq!(Note::parse("B7"), Note::B7);
+        assert_eq!(Note::parse("F#2"), Note(30));
+        assert_eq!(Note::parse("Gb2"), Note(30));
+        assert_eq!(Note::parse("C-4"), Note(48));
+        // sharps and flats can cross an octave
+        assert_eq!(Note::parse("B#3"), Note::parse("C4"));
+        assert_eq!(Note::parse("Cb4"), Note::parse("B3"));
+        assert_eq!(Note::new(9, 4), Note::A4);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn parse_below_c0() {
+        Note::parse("Cb0");
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn parse_above_b7() {
+        Note::parse("B#7");
+    }
+
+    #[test]
+    #[should_panic(expected = "octave must be 0-7")]
+    fn parse_octave_8() {
+        Note::parse("C8");
+    }
+
+    #[test]
+    #[should_panic(expected = "letter must be A-G")]
+    fn parse_bad_letter() {
+        Note::parse("H4");
+    }
+
+    #[test]
+    #[should_panic(expected = "accidental must be")]
+    fn parse_bad_accidental() {
+        Note::parse("Cx4");
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a name")]
+    fn parse_no_octave() {
+        Note::parse("C");
+    }
+
+    #[test]
+    fn transpose_stops_at_the_ends() {
+        assert_eq!(Note::A4.transpose(12), Note(69));
+        assert_eq!(Note::A4.transpose(-57), Note::C0);
+        assert_eq!(Note::A4.transpose(-100), Note::C0);
+        assert_eq!(Note::A4.transpose(100), Note::B7);
+        assert_eq!(Note::B7.transpose(1), Note::B7);
+        assert_eq!(Note::new(11, 9), Note::B7);
+    }
+
+    #[test]
+    fn periods_match_the_usual_tables() {
+        // A4 is 440 Hz, C4 middle C
+        assert_eq!(Note::A4.pulse_period(Region::Ntsc), 0x0FD);
+        assert_eq!(Note::parse("C4").pulse_period(Region::Ntsc), 0x1AB);
+        assert_eq!(Note::A4.pulse_period(Region::Pal), 0x0EB);
+        assert_eq!(Note::parse("C4").pulse_period(Region::Pal), 0x18C);
+        // an octave down on the triangle
+        assert_eq!(Note::A4.triangle_period(Region::Ntsc), 0x07E);
+        assert_eq!(Note::A4.triangle_period(Region::Pal), 0x075);
+        // A1 is the lowest a pulse can play on NTSC; PAL reaches lower
+        assert_eq!(Note::parse("A1").pulse_period(Region::Ntsc), 0x7F1);
+        assert_eq!(Note::parse("G#1").pulse_period(Region::Ntsc), MAX_PERIOD);
+        assert_eq!(Note::parse("G#1").pulse_period(Region::Pal), 0x7D1);
+        assert_eq!(Note::B7.pulse_period(Region::Ntsc), 27);
+        // out of range notes play the top one
+        assert_eq!(Note(200).pulse_period(Region::Ntsc), 27);
+    }
+
+    #[test]
+    fn detune_by_cents() {
+        assert_eq!(detune(253, 0), 253);
+        // 253 * 2^(-+50/1200) is 245.8 and 260.4
+        assert_eq!(detune(253, 50), 246);
+        assert_eq!(detune(253, -50), 260);
+        assert_eq!(detune(MAX_PERIOD, -100), MAX_PERIOD);
+        assert_eq!(detune(0, 100), 0);
+    }
+}
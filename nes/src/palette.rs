@@ -0,0 +1,341 @@
+// RAM copy of all 32 palette entries, uploaded during vblank, with fades,
+// flashes and the PPUMASK colour effects on top.
+//
+// Keep it in a static so the NMI can reach it:
+//     static mut PALETTE: Palette = Palette::new();
+//     // init, rendering off
+//     PALETTE.write(ppu::PAL_BG_0, &[0x0F, 0x29, 0x12, 0x19]);
+//     PALETTE.render();
+//     // main loop, once per frame
+//     PALETTE.update();
+//     // NMI, before the scroll is set
+//     PALETTE.render();
+//
+// Fades work in brightness steps from -4 (black) through 0 (the colours as
+// written) to 4 (white). Each step moves a colour one row of the NES
+// palette up or down.
+use crate::ppu::{self, Mask};
+
+pub const BLACK: u8 = 0x0F;
+pub const WHITE: u8 = 0x30;
+pub const MIN_BRIGHTNESS: i8 = -4;
+pub const MAX_BRIGHTNESS: i8 = 4;
+
+const ALL: u32 = 0xFFFF_FFFF;
+
+pub struct Palette {
+    colors: [u8; 32],
+    brightness: i8,
+    target: i8,
+    frames_per_step: u8,
+    timer: u8,
+    // entries showing `flash_color` instead, and for how many more frames
+    flash: u32,
+    flash_color: u8,
+    flash_frames: u8,
+    // entries that need uploading, one bit each
+    dirty: u32,
+    // greyscale and emphasis bits to apply at the next render
+    effects: Mask,
+    effects_dirty: bool,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// $3F10/$3F14/$3F18/$3F1C are the same memory as $3F00/$3F04/$3F08/$3F0C
+fn canonical(index: usize) -> usize {
+    if index & 0x13 == 0x10 {
+        index & 0x0F
+    } else {
+        index
+    }
+}
+
+fn darken(color: u8) -> u8 {
+    // columns D-F are black at every brightness
+    if color & 0x0F >= 0x0D || color < 0x10 {
+        BLACK
+    } else {
+        color - 0x10
+    }
+}
+
+fn lighten(color: u8) -> u8 {
+    if color & 0x0F >= 0x0D {
+        // black goes to dark grey
+        0x00
+    } else if color >= 0x30 {
+        WHITE
+    } else {
+        color + 0x10
+    }
+}
+
+pub fn adjust(color: u8, brightness: i8) -> u8 {
+    let mut color = color;
+    for _ in brightness..0 {
+        color = darken(color);
+    }
+    for _ in 0..brightness {
+        color = lighten(color);
+    }
+    color
+}
+
+#[allow(clippy::missing_safety_doc)]
+impl Palette {
+    pub const fn new() -> Self {
+        Self {
+            colors: [BLACK; 32],
+            brightness: 0,
+            target: 0,
+            frames_per_step: 0,
+            timer: 0,
+            flash: 0,
+            flash_color: 0,
+            flash_frames: 0,
+            dirty: ALL,
+            effects: Mask(0),
+            effects_dirty: false,
+        }
+    }
+
+    // same addresses as `ppu::write_bytes`, e.g. `ppu::PAL_SPRITE_0 + 1`
+    pub fn write(&mut self, addr: u16, colors: &[u8]) {
+        for (i, color) in colors.iter().enumerate() {
+            self.set((addr as usize + i) & 0x1F, *color);
+        }
+    }
+
+    pub fn set(&mut self, index: usize, color: u8) {
+        let index = canonical(index & 0x1F);
+        if self.colors[index] != color {
+            self.colors[index] = color;
+            self.dirty |= 1 << index;
+        }
+    }
+
+    pub fn get(&self, index: usize) -> u8 {
+        self.colors[canonical(index & 0x1F)]
+    }
+
+    // the colour actually on screen for an entry
+    pub fn shown(&self, index: usize) -> u8 {
+        let index = canonical(index & 0x1F);
+        if self.flash & (1 << index) != 0 {
+            self.flash_color
+        } else {
+            adjust(self.colors[index], self.brightness)
+        }
+    }
+
+    pub fn brightness(&self) -> i8 {
+        self.brightness
+    }
+
+    pub fn set_brightness(&mut self, brightness: i8) {
+        let brightness = brightness.clamp(MIN_BRIGHTNESS, MAX_BRIGHTNESS);
+        self.brightness = brightness;
+        self.target = brightness;
+        self.dirty = ALL;
+    }
+
+    // move one brightness step every `frames_per_step` frames until `target`
+    pub fn fade_to(&mut self, target: i8, frames_per_step: u8) {
+        self.target = target.clamp(MIN_BRIGHTNESS, MAX_BRIGHTNESS);
+        self.frames_per_step = frames_per_step;
+        self.timer = 0;
+    }
+
+    pub fn fade_to_black(&mut self, frames_per_step: u8) {
+        self.fade_to(MIN_BRIGHTNESS, frames_per_step);
+    }
+
+    pub fn fade_to_white(&mut self, frames_per_step: u8) {
+        self.fade_to(MAX_BRIGHTNESS, frames_per_step);
+    }
+
+    pub fn fade_from_black(&mut self, frames_per_step: u8) {
+        self.set_brightness(MIN_BRIGHTNESS);
+        self.fade_to(0, frames_per_step);
+    }
+
+    pub fn fade_from_white(&mut self, frames_per_step: u8) {
+        self.set_brightness(MAX_BRIGHTNESS);
+        self.fade_to(0, frames_per_step);
+    }
+
+    pub fn is_fading(&self) -> bool {
+        self.brightness != self.target
+    }
+
+    // show `color` in every entry for `frames` frames
+    pub fn flash(&mut self, color: u8, frames: u8) {
+        self.flash_entries(ALL, color, frames);
+    }
+
+    // flash one of the 8 sub-palettes (0-3 background, 4-7 sprites), e.g.
+    // the player's sprite palette when taking damage. The backdrop colour
+    // is left alone.
+    pub fn flash_palette(&mut self, palette: u8, color: u8, frames: u8) {
+        let entries = 0b1110 << ((palette as u32 & 7) * 4);
+        self.flash_entries(entries, color, frames);
+    }
+
+    fn flash_entries(&mut self, entries: u32, color: u8, frames: u8) {
+        self.dirty |= self.flash | entries;
+        self.flash = entries;
+        self.flash_color = color;
+        self.flash_frames = frames;
+    }
+
+    pub fn set_greyscale(&mut self, on: bool) {
+        self.effects = self.effects.set(Mask::GREYSCALE, on);
+        self.effects_dirty = true;
+    }
+
+    // any of `Mask::EMPHASIZE_*`
+    pub fn set_emphasis(&mut self, emphasis: Mask) {
+        self.effects = self.effects.without(Mask::EMPHASIS) | (emphasis & Mask::EMPHASIS);
+        self.effects_dirty = true;
+    }
+
+    // advance fades and flashes, once per frame
+    pub fn update(&mut self) {
+        if self.flash != 0 {
+            if self.flash_frames <= 1 {
+                self.dirty |= self.flash;
+                self.flash = 0;
+            } else {
+                self.flash_frames -= 1;
+            }
+        }
+
+        if self.brightness != self.target {
+            self.timer += 1;
+            if self.timer >= self.frames_per_step {
+                self.timer = 0;
+                self.brightness += (self.target - self.brightness).signum();
+                self.dirty = ALL;
+            }
+        }
+    }
+
+    // Upload changed entries. Call in NMI (or with rendering off) before
+    // the scroll is set, as this moves PPUADDR.
+    pub unsafe fn render(&mut self) {
+        if self.effects_dirty {
+            let keep = !(Mask::GREYSCALE | Mask::EMPHASIS);
+            ppu::write_mask((ppu::mask() & keep) | self.effects);
+            self.effects_dirty = false;
+        }
+        if self.dirty == 0 {
+            return;
+        }
+        // entries are written in runs, re-addressing after each gap
+        let mut next = usize::MAX;
+        for index in 0..32 {
+            if self.dirty & (1 << index) == 0 || canonical(index) != index {
+                continue;
+            }
+            if index != next {
+                ppu::write_addr(ppu::PAL_BG_0 + index as u16);
+            }
+            ppu::write_data(self.shown(index));
+            next = index + 1;
+        }
+        self.dirty = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_bus;
+
+    fn vram(index: u16) -> u8 {
+        mock_bus::with(|bus| bus.ppu.peek(ppu::PAL_BG_0 + index))
+    }
+
+    #[test]
+    fn sprite_backdrops_are_the_background_ones() {
+        mock_bus::reset();
+        let mut palette = Palette::new();
+        palette.set(0x10, 0x21);
+        palette.set(0x1C, 0x22);
+        palette.set(0x11, 0x23);
+        assert_eq!(palette.get(0x00), 0x21);
+        assert_eq!(palette.get(0x0C), 0x22);
+        assert_eq!(palette.get(0x01), BLACK);
+        unsafe {
+            palette.render();
+        }
+        assert_eq!((vram(0x00), vram(0x10)), (0x21, 0x21));
+        assert_eq!((vram(0x0C), vram(0x1C)), (0x22, 0x22));
+        assert_eq!((vram(0x01), vram(0x11)), (BLACK, 0x23));
+    }
+
+    #[test]
+    fn fades_step_a_row_at_a_time() {
+        mock_bus::reset();
+        let mut palette = Palette::new();
+        palette.write(ppu::PAL_BG_0, &[0x0F, 0x29, 0x30]);
+        palette.fade_to_black(2);
+        let mut shown = [[0; 2]; 4];
+        for colors in &mut shown {
+            palette.update();
+            palette.update();
+            unsafe {
+                palette.render();
+            }
+            *colors = [vram(1), vram(2)];
+        }
+        assert_eq!(
+            shown,
+            [[0x19, 0x20], [0x09, 0x10], [BLACK, 0x00], [BLACK, BLACK]]
+        );
+        assert!(!palette.is_fading());
+        // the colours themselves are kept
+        assert_eq!(palette.get(1), 0x29);
+
+        palette.fade_to(0, 1);
+        for _ in 0..4 {
+            palette.update();
+        }
+        unsafe {
+            palette.render();
+        }
+        assert_eq!([vram(1), vram(2)], [0x29, 0x30]);
+    }
+
+    #[test]
+    fn flash_palette_puts_the_colours_back() {
+        mock_bus::reset();
+        let mut palette = Palette::new();
+        palette.write(ppu::PAL_SPRITE_0 + 4, &[0x01, 0x16, 0x27, 0x38]);
+        palette.flash_palette(5, WHITE, 2);
+        unsafe {
+            palette.render();
+        }
+        // the backdrop isn't part of a sub-palette
+        assert_eq!(
+            [vram(0x14), vram(0x15), vram(0x16), vram(0x17)],
+            [0x01, WHITE, WHITE, WHITE]
+        );
+        assert_eq!(vram(0x19), BLACK);
+        palette.update();
+        unsafe {
+            palette.render();
+        }
+        assert_eq!(vram(0x15), WHITE);
+        palette.update();
+        unsafe {
+            palette.render();
+        }
+        assert_eq!([vram(0x15), vram(0x16), vram(0x17)], [0x16, 0x27, 0x38]);
+    }
+}
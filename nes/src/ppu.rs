@@ -1,9 +1,13 @@
-use crate::addr::Addr;
+pub use crate::ppu_regs::{Ctrl, Mask, Status};
+use crate::{
+    addr::Addr,
+    bus::shadow,
+    charset::{self, Charset},
+    rle,
+};
 
-const _CTRL_NMI: u8 = 0b10000000;
 const PPU_CTRL: Addr = Addr(0x2000);
 const PPU_MASK: Addr = Addr(0x2001);
-#[allow(dead_code)]
 const PPU_STATUS: Addr = Addr(0x2002);
 const PPU_SCROLL: Addr = Addr(0x2005);
 const PPU_ADDR: Addr = Addr(0x2006);
@@ -11,58 +15,130 @@ const PPU_DATA: Addr = Addr(0x2007);
 
 // all calls to PPU are unsafe because they may only
 // be safely made during the vblank interval
+#[allow(clippy::missing_safety_doc)]
 pub unsafe fn reset() {
+    write_ctrl(ctrl().with_nametable(0));
     write_addr(PPU_CTRL.addr());
     scroll(0, 0);
 }
-pub unsafe fn write_ctrl(value: u8) {
-    PPU_CTRL.write(value)
+
+// how the cart wires the four logical nametables onto the 2 KiB of VRAM
+#[derive(Copy, Clone, PartialEq)]
+pub enum Mirroring {
+    // $2000 = $2400, $2800 = $2C00 (vertical arrangement)
+    Horizontal,
+    // $2000 = $2800, $2400 = $2C00 (horizontal arrangement)
+    Vertical,
+    FourScreen,
+}
+
+// PPUCTRL and PPUMASK are write only, so keep a copy of what we last wrote
+shadow!(static CTRL: Ctrl = Ctrl(0));
+shadow!(static MASK: Mask = Mask(0));
+
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn write_ctrl(value: Ctrl) {
+    CTRL.set(value);
+    PPU_CTRL.write(value.bits())
+}
+
+pub fn ctrl() -> Ctrl {
+    CTRL.get()
+}
+
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn and_ctrl(value: Ctrl) {
+    write_ctrl(ctrl() & value);
+}
+
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn or_ctrl(value: Ctrl) {
+    write_ctrl(ctrl() | value);
 }
 
-pub unsafe fn and_ctrl(value: u8) {
-    let next = PPU_CTRL.read() & value;
-    PPU_CTRL.write(next);
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn write_mask(value: Mask) {
+    MASK.set(value);
+    PPU_MASK.write(value.bits());
 }
 
-pub unsafe fn or_ctrl(value: u8) {
-    let next = PPU_CTRL.read() | value;
-    PPU_CTRL.write(next);
+pub fn mask() -> Mask {
+    MASK.get()
 }
 
-pub unsafe fn write_mask(value: u8) {
-    PPU_MASK.write(value);
+// clears the vblank flag and the $2005/$2006 write toggle as a side effect
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn read_status() -> Status {
+    Status(PPU_STATUS.read())
 }
 
+#[allow(clippy::missing_safety_doc)]
 pub unsafe fn write_addr_byte(value: u8) {
     PPU_ADDR.write(value);
 }
 
+#[allow(clippy::missing_safety_doc)]
 pub unsafe fn write_addr(value: u16) {
     write_addr_byte((value >> 8) as u8);
     write_addr_byte(value as u8);
 }
 
+#[allow(clippy::missing_safety_doc)]
 pub unsafe fn write_data(value: u8) {
     PPU_DATA.write(value);
 }
 
+#[allow(clippy::missing_safety_doc)]
 pub unsafe fn scroll(x: u8, y: u8) {
     PPU_SCROLL.write(x);
     PPU_SCROLL.write(y)
 }
 
+// Split screen: sprite 0 is placed over an opaque background pixel on the
+// scanline where the split goes, e.g. the bottom row of a HUD. The NMI
+// sets the scroll for the top part as usual; then, right after
+// `io::wait_for_vblank`, the main loop calls
+//     ppu::wait_sprite_zero();
+//     ppu::split_scroll(nametable, x, y);
+// to switch to the bottom part. Reserve the sprite with
+// `SpriteState::reserve_sprite_zero`.
+// See https://www.nesdev.org/wiki/PPU_OAM#Sprite_zero_hits
+
+// Busy-waits for this frame's sprite 0 hit. Never returns if sprite 0 is
+// off screen, over transparent background or rendering is disabled.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn wait_sprite_zero() {
+    // last frame's hit stays set until the end of vblank
+    while read_status().contains(Status::SPRITE_0_HIT) {}
+    while !read_status().contains(Status::SPRITE_0_HIT) {}
+}
+
+// Change both scroll axes mid-frame. Writing PPUSCROLL alone only affects X
+// once rendering has started, so this uses the PPUADDR/PPUSCROLL sequence
+// from https://www.nesdev.org/wiki/PPU_scrolling#Split_X/Y_scroll
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn split_scroll(nametable: u8, x: u8, y: u8) {
+    write_addr_byte((nametable & 0b11) << 2);
+    PPU_SCROLL.write(y);
+    PPU_SCROLL.write(x);
+    write_addr_byte(((y & 0xF8) << 2) | (x >> 3));
+}
+
 // TODO: are enable / disable nmi safe?
+#[allow(clippy::missing_safety_doc)]
 pub unsafe fn enable_nmi() {
-    write_ctrl(0x80);
-    write_mask(0x1E);
+    or_ctrl(Ctrl::NMI);
+    write_mask(mask() | Mask::RENDER);
 }
 
+#[allow(clippy::missing_safety_doc)]
 pub unsafe fn disable_nmi() {
-    write_mask(0);
-    write_ctrl(0);
+    write_mask(mask() & !Mask::RENDER);
+    and_ctrl(!Ctrl::NMI);
 }
 
 #[inline(never)]
+#[allow(clippy::missing_safety_doc)]
 pub unsafe fn clear_nametable() {
     write_addr(PPU_CTRL.addr());
     for _ in 0..0x400 {
@@ -70,12 +146,27 @@ pub unsafe fn clear_nametable() {
     }
 }
 
+// runtime conversion; prefer `draw_tiles(text!(..))` for fixed strings
+#[allow(clippy::missing_safety_doc)]
 pub unsafe fn draw_text(text: &str) {
+    draw_text_with(&charset::ASCII, text);
+}
+
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn draw_text_with(charset: &Charset, text: &str) {
     for ch in text.chars() {
-        write_data(ch as u8 - 32);
+        write_data(charset.tile_or_blank(ch));
     }
 }
 
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn draw_tiles(tiles: &[u8]) {
+    for tile in tiles {
+        write_data(*tile);
+    }
+}
+
+#[allow(clippy::missing_safety_doc)]
 pub unsafe fn draw_ascii(off: u16, ascii: &str) {
     for (i, line) in ascii.split("\n").enumerate() {
         write_addr(off + (0x20 * i as u16));
@@ -85,6 +176,7 @@ pub unsafe fn draw_ascii(off: u16, ascii: &str) {
 
 pub const STR_OFFSET: u8 = 0x10;
 
+#[allow(clippy::missing_safety_doc)]
 pub unsafe fn write_bytes(offset: u16, pal: &[u8]) {
     write_addr(offset);
 
@@ -93,13 +185,24 @@ pub unsafe fn write_bytes(offset: u16, pal: &[u8]) {
     });
 }
 
+// Unpack `rle::encode`d data straight to VRAM, e.g. a whole nametable with
+// its attribute table. Rendering must be off, it's far too slow for vblank.
+#[inline(never)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn write_rle(offset: u16, data: &[u8]) {
+    write_addr(offset);
+    for byte in rle::decode(data) {
+        write_data(byte);
+    }
+}
+
 #[allow(dead_code)]
 pub const PAL_BG_0: u16 = 0x3f00;
 #[allow(dead_code)]
 pub const PAL_BG_1: u16 = 0x3f04;
 #[allow(dead_code)]
 pub const PAL_BG_2: u16 = 0x3f08;
-#[allow(dead_code)]
+#[allow(dead_code, clippy::mixed_case_hex_literals)]
 pub const PAL_BG_3: u16 = 0x3f0C;
 #[allow(dead_code)]
 pub const PAL_SPRITE_0: u16 = 0x3f10;
@@ -107,5 +210,65 @@ pub const PAL_SPRITE_0: u16 = 0x3f10;
 pub const PAL_SPRITE_1: u16 = 0x3f14;
 #[allow(dead_code)]
 pub const PAL_SPRITE_2: u16 = 0x3f18;
-#[allow(dead_code)]
+#[allow(dead_code, clippy::mixed_case_hex_literals)]
 pub const PAL_SPRITE_3: u16 = 0x3f1C;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_bus;
+
+    #[test]
+    fn split_scroll_waits_for_this_frames_hit() {
+        mock_bus::reset();
+        mock_bus::with(|bus| {
+            // sprite 0 on screen
+            bus.ppu.oam[0] = 100;
+            // last frame's hit, still set going into vblank
+            bus.ppu.status = Status::SPRITE_0_HIT.bits();
+        });
+        unsafe {
+            enable_nmi();
+            PPU_SCROLL.write(8);
+            PPU_SCROLL.write(16);
+        }
+        mock_bus::vblank();
+        unsafe {
+            wait_sprite_zero();
+        }
+        mock_bus::with(|bus| {
+            assert!(Status(bus.ppu.status).contains(Status::SPRITE_0_HIT));
+            // the top part's scroll is still there
+            assert_eq!((bus.ppu.scroll_x, bus.ppu.scroll_y), (8, 16));
+        });
+        unsafe {
+            split_scroll(1, 0x2D, 0x48);
+        }
+        mock_bus::with(|bus| {
+            assert_eq!((bus.ppu.scroll_x, bus.ppu.scroll_y), (0x2D, 0x48));
+            // nametable 1, coarse Y 9, coarse X 5
+            assert_eq!(bus.ppu.vram_addr, 0x0425);
+        });
+    }
+
+    #[test]
+    fn enable_nmi_keeps_the_other_bits() {
+        mock_bus::reset();
+        unsafe {
+            write_ctrl(Ctrl::SPRITE_8X16);
+            write_mask(Mask::GREYSCALE);
+            enable_nmi();
+        }
+        mock_bus::with(|bus| {
+            assert_eq!(bus.ppu.ctrl, (Ctrl::NMI | Ctrl::SPRITE_8X16).bits());
+            assert_eq!(bus.ppu.mask, (Mask::RENDER | Mask::GREYSCALE).bits());
+        });
+        unsafe {
+            disable_nmi();
+        }
+        mock_bus::with(|bus| {
+            assert_eq!(bus.ppu.ctrl, Ctrl::SPRITE_8X16.bits());
+            assert_eq!(bus.ppu.mask, Mask::GREYSCALE.bits());
+        });
+    }
+}
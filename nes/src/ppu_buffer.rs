@@ -1,6 +1,16 @@
 use core::iter;
 
-use crate::{addr::Addr, capped_vec::CappedVec, ppu};
+use crate::{
+    addr::Addr,
+    capped_vec::CappedVec,
+    charset::{self, Charset},
+    ppu::{self, Ctrl},
+};
+
+// set in the length byte for segments drawn top to bottom
+const VERTICAL: u8 = 0x80;
+// which leaves 7 bits for the length
+const MAX_SEGMENT: u8 = 0x7F;
 
 enum State {
     GetLength,
@@ -16,6 +26,7 @@ pub type Buffer<const N: usize> = CappedVec<u8, N>;
 // this is the only way I can think of to provide an interface to a static
 // mutable buffer. Implementers define the buffer and provide a ref to it
 // by implementing buffer().
+#[allow(clippy::missing_safety_doc)]
 pub trait BufferTrait<const N: usize> {
     const BUFFER_SIZE: usize = N;
     unsafe fn buffer() -> &'static mut Buffer<N>;
@@ -24,11 +35,20 @@ pub trait BufferTrait<const N: usize> {
         // Each segment of consecutive data stored as
         // length | start addr high | start addr low | tile data ...
         // we don't need a terminating byte with len = 0 because we only
-        // iterate over the values that have been pushed to the buffer
+        // iterate over the values that have been pushed to the buffer.
+        // The top bit of the length selects the +32 VRAM increment.
         let mut state = State::GetLength;
+        let mut vertical = false;
         for b in unsafe { Self::buffer() } {
             state = match state {
-                State::GetLength => State::WriteAddrHigh(*b),
+                State::GetLength => {
+                    let is_vertical = *b & VERTICAL != 0;
+                    if is_vertical != vertical {
+                        vertical = is_vertical;
+                        ppu::write_ctrl(ppu::ctrl().set(Ctrl::VRAM_INC_32, vertical));
+                    }
+                    State::WriteAddrHigh(*b & !VERTICAL)
+                }
                 State::WriteAddrHigh(len) => {
                     ppu::write_addr_byte(*b);
                     State::WriteAddrLow(len)
@@ -46,33 +66,47 @@ pub trait BufferTrait<const N: usize> {
                 }
             };
         }
+        if vertical {
+            ppu::and_ctrl(!Ctrl::VRAM_INC_32);
+        }
+    }
+    // runs of more than 127 tiles are split into several segments
+    fn tiles<I: Iterator<Item = u8>>(addr: Addr, xs: I) {
+        Self::segment(addr, xs, 0)
     }
-    fn tiles<I: Iterator<Item = u8>>(addr: Addr, mut xs: I) {
-        // if empty do nothing
-        let first = match xs.next() {
-            Some(t) => t,
-            None => return,
-        };
+    // like tiles, but each tile goes one row below the previous one
+    fn column<I: Iterator<Item = u8>>(addr: Addr, xs: I) {
+        Self::segment(addr, xs, VERTICAL)
+    }
+    fn segment<I: Iterator<Item = u8>>(mut addr: Addr, xs: I, flags: u8) {
         let buffer = unsafe { Self::buffer() };
-        let start_index = buffer.len();
-        buffer.push(0); // skip for now, write after we've counted the tiles
-
-        // push high then low address bytes
-        buffer.push((addr.addr() >> 8) as u8);
-        buffer.push(addr.addr() as u8);
-
-        // push first tile
-        buffer.push(first);
-        let mut n_tiles: u8 = 1;
-
-        // push remaining tiles
+        let step = if flags & VERTICAL != 0 { 32 } else { 1 };
+        // the length byte's index, None until there's a tile to push
+        let mut start_index = None;
+        let mut n_tiles: u8 = 0;
         for x in xs {
+            if start_index.is_none() || n_tiles == MAX_SEGMENT {
+                if let Some(index) = start_index {
+                    // full, carry on in a new segment after this one
+                    buffer.write(index, n_tiles | flags);
+                    addr.add(n_tiles as isize * step);
+                }
+                start_index = Some(buffer.len());
+                buffer.push(0); // skip for now, write after we've counted the tiles
+
+                // push high then low address bytes
+                buffer.push((addr.addr() >> 8) as u8);
+                buffer.push(addr.addr() as u8);
+                n_tiles = 0;
+            }
             buffer.push(x);
             n_tiles += 1;
         }
 
         // write number of tiles
-        buffer.write(start_index, n_tiles);
+        if let Some(index) = start_index {
+            buffer.write(index, n_tiles | flags);
+        }
     }
 
     fn tile(addr: Addr, x: u8) {
@@ -83,7 +117,79 @@ pub trait BufferTrait<const N: usize> {
         unsafe { Self::buffer() }.clear()
     }
 
+    // bytes left this frame; each segment costs 3 plus one per tile
+    fn remaining() -> usize {
+        N - unsafe { Self::buffer() }.len()
+    }
+
     fn draw_text(addr: Addr, text: &str) {
-        Self::tiles(addr, text.chars().map(|c| c as u8 - 32))
+        Self::draw_text_with(addr, &charset::ASCII, text)
+    }
+
+    fn draw_text_with(addr: Addr, charset: &Charset, text: &str) {
+        Self::tiles(addr, text.chars().map(|c| charset.tile_or_blank(c)))
+    }
+
+    // e.g. `Buffer::draw_tiles(addr, text!("SCORE"))`
+    fn draw_tiles(addr: Addr, tiles: &[u8]) {
+        Self::tiles(addr, tiles.iter().copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::{boxed::Box, thread_local};
+
+    use super::*;
+    use crate::mock_bus;
+
+    struct TestBuffer;
+
+    thread_local! {
+        static BUFFER: *mut Buffer<256> = Box::into_raw(Box::new(Buffer::new()));
+    }
+
+    impl BufferTrait<256> for TestBuffer {
+        unsafe fn buffer() -> &'static mut Buffer<256> {
+            BUFFER.with(|buffer| &mut **buffer)
+        }
+    }
+
+    #[test]
+    fn rows_and_columns_reach_vram() {
+        mock_bus::reset();
+        TestBuffer::clear();
+        TestBuffer::draw_tiles(Addr(0x2021), &[1, 2, 3]);
+        TestBuffer::column(Addr(0x2040), [4, 5].into_iter());
+        assert_eq!(TestBuffer::remaining(), 256 - 6 - 5);
+        unsafe {
+            TestBuffer::render();
+        }
+        mock_bus::with(|bus| {
+            let vram = |addr| bus.ppu.peek(addr);
+            assert_eq!([vram(0x2021), vram(0x2022), vram(0x2023)], [1, 2, 3]);
+            assert_eq!([vram(0x2040), vram(0x2060)], [4, 5]);
+            // left at +1 afterwards
+            assert!(!Ctrl(bus.ppu.ctrl).contains(Ctrl::VRAM_INC_32));
+        });
+    }
+
+    #[test]
+    fn long_runs_are_split() {
+        mock_bus::reset();
+        TestBuffer::clear();
+        TestBuffer::tiles(Addr(0x2000), (0..130).map(|i| i as u8 + 1));
+        assert_eq!(TestBuffer::remaining(), 256 - (3 + 127) - (3 + 3));
+        unsafe {
+            TestBuffer::render();
+        }
+        mock_bus::with(|bus| {
+            for i in 0..130 {
+                assert_eq!(bus.ppu.peek(0x2000 + i), i as u8 + 1);
+            }
+            assert_eq!(bus.ppu.peek(0x2000 + 130), 0);
+        });
     }
 }
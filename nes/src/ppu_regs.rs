@@ -0,0 +1,117 @@
+// Typed values for the PPU's control registers. `flags!` is shared with
+// the APU status register.
+// See https://www.nesdev.org/wiki/PPU_registers
+macro_rules! flags {
+    ($name:ident) => {
+        #[derive(Copy, Clone, PartialEq, Eq, Default)]
+        pub struct $name(pub u8);
+        impl $name {
+            pub const fn bits(self) -> u8 {
+                self.0
+            }
+            pub const fn contains(self, other: Self) -> bool {
+                self.0 & other.0 == other.0
+            }
+            pub const fn intersects(self, other: Self) -> bool {
+                self.0 & other.0 != 0
+            }
+            pub const fn with(self, other: Self) -> Self {
+                Self(self.0 | other.0)
+            }
+            pub const fn without(self, other: Self) -> Self {
+                Self(self.0 & !other.0)
+            }
+            pub const fn set(self, other: Self, on: bool) -> Self {
+                if on {
+                    self.with(other)
+                } else {
+                    self.without(other)
+                }
+            }
+        }
+        impl core::ops::BitOr for $name {
+            type Output = Self;
+            fn bitor(self, rhs: Self) -> Self {
+                self.with(rhs)
+            }
+        }
+        impl core::ops::BitAnd for $name {
+            type Output = Self;
+            fn bitand(self, rhs: Self) -> Self {
+                Self(self.0 & rhs.0)
+            }
+        }
+        impl core::ops::Not for $name {
+            type Output = Self;
+            fn not(self) -> Self {
+                Self(!self.0)
+            }
+        }
+        impl core::ops::BitOrAssign for $name {
+            fn bitor_assign(&mut self, rhs: Self) {
+                *self = *self | rhs;
+            }
+        }
+        impl core::ops::BitAndAssign for $name {
+            fn bitand_assign(&mut self, rhs: Self) {
+                *self = *self & rhs;
+            }
+        }
+    };
+}
+pub(crate) use flags;
+
+flags!(Ctrl);
+flags!(Mask);
+flags!(Status);
+
+// $2000, write only
+impl Ctrl {
+    // base nametable, bits 0-1
+    pub const NAMETABLE_2000: Ctrl = Ctrl(0b00);
+    pub const NAMETABLE_2400: Ctrl = Ctrl(0b01);
+    pub const NAMETABLE_2800: Ctrl = Ctrl(0b10);
+    pub const NAMETABLE_2C00: Ctrl = Ctrl(0b11);
+    pub const NAMETABLE_MASK: Ctrl = Ctrl(0b11);
+    // add 32 (down a row) after each $2007 access instead of 1
+    pub const VRAM_INC_32: Ctrl = Ctrl(0b100);
+    // 8x8 sprites come from $1000 instead of $0000
+    pub const SPRITE_TABLE_1000: Ctrl = Ctrl(0b1000);
+    pub const BG_TABLE_1000: Ctrl = Ctrl(0b10000);
+    pub const SPRITE_8X16: Ctrl = Ctrl(0b100000);
+    pub const NMI: Ctrl = Ctrl(0b10000000);
+
+    pub const fn nametable(self) -> u8 {
+        self.0 & Self::NAMETABLE_MASK.0
+    }
+    pub const fn with_nametable(self, nametable: u8) -> Self {
+        Self(self.without(Self::NAMETABLE_MASK).0 | (nametable & Self::NAMETABLE_MASK.0))
+    }
+}
+
+// $2001, write only
+impl Mask {
+    pub const GREYSCALE: Mask = Mask(0b1);
+    // show in the leftmost 8 pixels, i.e. don't clip
+    pub const SHOW_BG_LEFT: Mask = Mask(0b10);
+    pub const SHOW_SPRITES_LEFT: Mask = Mask(0b100);
+    pub const SHOW_BG: Mask = Mask(0b1000);
+    pub const SHOW_SPRITES: Mask = Mask(0b10000);
+    pub const EMPHASIZE_RED: Mask = Mask(0b100000);
+    pub const EMPHASIZE_GREEN: Mask = Mask(0b1000000);
+    pub const EMPHASIZE_BLUE: Mask = Mask(0b10000000);
+
+    pub const RENDER: Mask = Mask(
+        Self::SHOW_BG_LEFT.0 | Self::SHOW_SPRITES_LEFT.0 | Self::SHOW_BG.0 | Self::SHOW_SPRITES.0,
+    );
+    pub const EMPHASIS: Mask =
+        Mask(Self::EMPHASIZE_RED.0 | Self::EMPHASIZE_GREEN.0 | Self::EMPHASIZE_BLUE.0);
+}
+
+// $2002, read only. Reading clears VBLANK and the $2005/$2006 write toggle.
+impl Status {
+    // more than 8 sprites on a scanline (buggy on hardware)
+    pub const SPRITE_OVERFLOW: Status = Status(0b100000);
+    pub const SPRITE_0_HIT: Status = Status(0b1000000);
+    pub const VBLANK: Status = Status(0b10000000);
+}
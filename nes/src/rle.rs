@@ -0,0 +1,172 @@
+// RLE compression in the neslib / NES Screen Tool format, mostly for full
+// 1024 byte nametables (tiles + attributes).
+//
+// The first byte is a tag value that doesn't otherwise appear in the data.
+// After that, any other byte is a literal. The tag followed by a count
+// repeats the previous literal `count` more times, and the tag followed by 0
+// ends the data.
+//
+// Encoding is const, so screens can be compressed at compile time and only
+// the packed bytes end up in ROM:
+//     const RAW: &[u8] = include_bytes!("title.nam");
+//     const TITLE: [u8; rle::encoded_len(RAW)] = rle::encode(RAW);
+//     ppu::write_rle(0x2000, &TITLE);
+
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    tag: u8,
+    last: u8,
+    repeat: u8,
+}
+
+pub fn decode(data: &[u8]) -> Decoder<'_> {
+    Decoder {
+        data,
+        pos: 1,
+        tag: data.first().copied().unwrap_or(0),
+        last: 0,
+        repeat: 0,
+    }
+}
+
+impl Iterator for Decoder<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.repeat > 0 {
+            self.repeat -= 1;
+            return Some(self.last);
+        }
+        let byte = *self.data.get(self.pos)?;
+        self.pos += 1;
+        if byte != self.tag {
+            self.last = byte;
+            return Some(byte);
+        }
+        match self.data.get(self.pos) {
+            Some(&count) if count > 0 => {
+                self.pos += 1;
+                self.repeat = count - 1;
+                Some(self.last)
+            }
+            _ => {
+                self.pos = self.data.len();
+                None
+            }
+        }
+    }
+}
+
+// the lowest byte value that doesn't occur in the data
+const fn pick_tag(data: &[u8]) -> u8 {
+    let mut used = [false; 256];
+    let mut i = 0;
+    while i < data.len() {
+        used[data[i] as usize] = true;
+        i += 1;
+    }
+    let mut tag = 0;
+    while tag < 256 {
+        if !used[tag] {
+            return tag as u8;
+        }
+        tag += 1;
+    }
+    panic!("rle: data uses all 256 byte values, no tag available");
+}
+
+// number of bytes equal to data[start] from start on, at most 256
+const fn run_length(data: &[u8], start: usize) -> usize {
+    let mut end = start + 1;
+    while end < data.len() && end - start < 256 && data[end] == data[start] {
+        end += 1;
+    }
+    end - start
+}
+
+pub const fn encoded_len(data: &[u8]) -> usize {
+    // tag, and the tag + 0 terminator
+    let mut len = 3;
+    let mut i = 0;
+    while i < data.len() {
+        let run = run_length(data, i);
+        len += match run {
+            1 => 1,
+            2 => 2,
+            _ => 3,
+        };
+        i += run;
+    }
+    len
+}
+
+pub const fn encode<const N: usize>(data: &[u8]) -> [u8; N] {
+    if encoded_len(data) != N {
+        panic!("rle: output size must be encoded_len(data)");
+    }
+    let tag = pick_tag(data);
+    let mut out = [0; N];
+    out[0] = tag;
+    let mut o = 1;
+    let mut i = 0;
+    while i < data.len() {
+        let run = run_length(data, i);
+        out[o] = data[i];
+        o += 1;
+        if run == 2 {
+            // a second literal is shorter than tag + count
+            out[o] = data[i];
+            o += 1;
+        } else if run > 2 {
+            out[o] = tag;
+            out[o + 1] = (run - 1) as u8;
+            o += 2;
+        }
+        i += run;
+    }
+    out[o] = tag;
+    out[o + 1] = 0;
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    // a 300 byte run, each of 0..200 once, then runs of three
+    const fn sample() -> [u8; 600] {
+        let mut data = [5; 600];
+        let mut i = 0;
+        while i < 200 {
+            data[300 + i] = i as u8;
+            i += 1;
+        }
+        while i < 300 {
+            data[300 + i] = (i / 3) as u8 + 150;
+            i += 1;
+        }
+        data
+    }
+    const SAMPLE: [u8; 600] = sample();
+    const PACKED: [u8; encoded_len(&SAMPLE)] = encode(&SAMPLE);
+
+    #[test]
+    fn round_trip() {
+        // 0..200 are all used
+        assert_eq!(PACKED[0], 200);
+        // 300 5s as a run of 256 and one of 44
+        assert_eq!(PACKED[1..7], [5, 200, 255, 5, 200, 43]);
+        assert_eq!(decode(&PACKED).collect::<Vec<u8>>(), SAMPLE);
+    }
+
+    #[test]
+    fn empty() {
+        const PACKED: [u8; 3] = encode(&[]);
+        assert_eq!(PACKED, [0, 0, 0]);
+        assert_eq!(decode(&PACKED).count(), 0);
+    }
+}
@@ -0,0 +1,287 @@
+// Camera over a world larger than one screen.
+// See https://www.nesdev.org/wiki/PPU_scrolling
+//
+// Two nametables give a 512x240 (vertical mirroring) or 256x480 (horizontal
+// mirroring) ring of tiles. As the camera moves, the column or row that is
+// about to come into view is redrawn from a `TileSource` through the ppu
+// buffer, so the world can be any size.
+//
+// Attributes aren't streamed; update them with `attributes::AttributeTable`.
+use core::ops::Range;
+
+use crate::{
+    addr::Addr,
+    ppu::{self, Mirroring},
+    ppu_buffer::BufferTrait,
+};
+
+pub const SCREEN_WIDTH: u16 = 256;
+pub const SCREEN_HEIGHT: u16 = 240;
+const COLUMNS: u16 = 32;
+const ROWS: u16 = 30;
+
+// the whole level in tile units
+pub trait TileSource {
+    fn tile(&self, x: u16, y: u16) -> u8;
+}
+
+pub struct Scroll {
+    x: u16,
+    y: u16,
+    mirroring: Mirroring,
+}
+
+#[allow(clippy::missing_safety_doc)]
+impl Scroll {
+    // four-screen is treated like vertical mirroring (horizontal scrolling)
+    pub const fn new(mirroring: Mirroring) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            mirroring,
+        }
+    }
+
+    pub fn x(&self) -> u16 {
+        self.x
+    }
+
+    pub fn y(&self) -> u16 {
+        self.y
+    }
+
+    fn horizontal(&self) -> bool {
+        self.mirroring != Mirroring::Horizontal
+    }
+
+    // draw everything visible from the current position; rendering must be off
+    pub unsafe fn draw_all<S: TileSource>(&self, source: &S) {
+        if self.horizontal() {
+            let first = self.x / 8;
+            ppu::or_ctrl(ppu::Ctrl::VRAM_INC_32);
+            for column in first..=first + COLUMNS {
+                ppu::write_addr(column_addr(column).addr());
+                for row in 0..ROWS {
+                    ppu::write_data(source.tile(column, row));
+                }
+            }
+            ppu::and_ctrl(!ppu::Ctrl::VRAM_INC_32);
+        } else {
+            let first = self.y / 8;
+            for row in first..=first + ROWS {
+                ppu::write_addr(row_addr(row).addr());
+                for column in 0..COLUMNS {
+                    ppu::write_data(source.tile(column, row));
+                }
+            }
+        }
+    }
+
+    // Move the camera, queueing any newly exposed columns (vertical mirroring)
+    // or rows (horizontal mirroring). Each costs 33 or 35 bytes of buffer,
+    // and a jump of a screen or more exposes a whole screen's worth. If
+    // they don't all fit in what's left of the buffer, nothing is queued
+    // and this returns false: redraw with `draw_all` while rendering is off
+    // (a teleport or room change, say). Movement along the other axis is
+    // limited to one screen.
+    pub fn move_to<B: BufferTrait<N>, S: TileSource, const N: usize>(
+        &mut self,
+        x: u16,
+        y: u16,
+        source: &S,
+    ) -> bool {
+        if self.horizontal() {
+            let columns = exposed(self.x / 8, x / 8, COLUMNS);
+            self.x = x;
+            self.y = y % SCREEN_HEIGHT;
+            if columns.len() * (3 + ROWS as usize) > B::remaining() {
+                return false;
+            }
+            for column in columns {
+                queue_column::<B, S, N>(column, source);
+            }
+        } else {
+            let rows = exposed(self.y / 8, y / 8, ROWS);
+            self.x = x % SCREEN_WIDTH;
+            self.y = y;
+            if rows.len() * (3 + COLUMNS as usize) > B::remaining() {
+                return false;
+            }
+            for row in rows {
+                queue_row::<B, S, N>(row, source);
+            }
+        }
+        true
+    }
+
+    // Call at the end of NMI, after all PPUADDR writes. Sets the nametable
+    // select bits and the fine scroll.
+    pub unsafe fn apply(&self) {
+        let (nametable, x, y) = if self.horizontal() {
+            (
+                (self.x / SCREEN_WIDTH) as u8 & 1,
+                self.x as u8,
+                self.y as u8,
+            )
+        } else {
+            let y = self.y % (SCREEN_HEIGHT * 2);
+            let nametable = (y / SCREEN_HEIGHT) as u8;
+            (nametable << 1, self.x as u8, (y % SCREEN_HEIGHT) as u8)
+        };
+        ppu::write_ctrl(ppu::ctrl().with_nametable(nametable));
+        ppu::scroll(x, y);
+    }
+}
+
+// The columns or rows visible with the camera at tile `new` that weren't
+// at `old`, where a screen shows `span` + 1 of them counting the one
+// partly scrolled in. Moving right or down that's past the far edge,
+// moving left or up the near edge itself.
+fn exposed(old: u16, new: u16, span: u16) -> Range<u16> {
+    if new >= old {
+        (old + span + 1).max(new)..new + span + 1
+    } else {
+        new..old.min(new + span + 1)
+    }
+}
+
+// top of world column `column` in the left/right nametable pair
+fn column_addr(column: u16) -> Addr {
+    let nametable = (column / COLUMNS) & 1;
+    Addr(0x2000 + nametable * 0x400 + column % COLUMNS)
+}
+
+// start of world row `row` in the top/bottom nametable pair
+fn row_addr(row: u16) -> Addr {
+    let row = row % (ROWS * 2);
+    let nametable = row / ROWS;
+    Addr(0x2000 + nametable * 0x800 + (row % ROWS) * COLUMNS)
+}
+
+fn queue_column<B: BufferTrait<N>, S: TileSource, const N: usize>(column: u16, source: &S) {
+    B::column(
+        column_addr(column),
+        (0..ROWS).map(|row| source.tile(column, row)),
+    );
+}
+
+fn queue_row<B: BufferTrait<N>, S: TileSource, const N: usize>(row: u16, source: &S) {
+    B::tiles(
+        row_addr(row),
+        (0..COLUMNS).map(|column| source.tile(column, row)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::{boxed::Box, thread_local, vec::Vec};
+
+    use super::*;
+    use crate::{mock_bus, ppu::Ctrl, ppu_buffer::Buffer};
+
+    struct TestBuffer;
+
+    thread_local! {
+        static BUFFER: *mut Buffer<128> = Box::into_raw(Box::new(Buffer::new()));
+    }
+
+    impl BufferTrait<128> for TestBuffer {
+        unsafe fn buffer() -> &'static mut Buffer<128> {
+            BUFFER.with(|buffer| &mut **buffer)
+        }
+    }
+
+    // every tile is the low byte of its column
+    struct Stripes;
+
+    impl TileSource for Stripes {
+        fn tile(&self, x: u16, _y: u16) -> u8 {
+            x as u8
+        }
+    }
+
+    // (length byte, address) of each queued segment
+    fn segments() -> Vec<(u8, u16)> {
+        let buffer = unsafe { TestBuffer::buffer() };
+        let mut segments = Vec::new();
+        let mut at = 0;
+        while at < buffer.len() {
+            let len = *buffer.read(at);
+            let addr = (*buffer.read(at + 1) as u16) << 8 | *buffer.read(at + 2) as u16;
+            segments.push((len, addr));
+            at += 3 + (len & 0x7F) as usize;
+        }
+        segments
+    }
+
+    #[test]
+    fn exposed_lines() {
+        assert_eq!(exposed(0, 1, 32), 33..34);
+        assert_eq!(exposed(2, 1, 32), 1..2);
+        assert!(exposed(5, 5, 32).is_empty());
+        // a jump of more than a screen exposes just one screen
+        assert_eq!(exposed(0, 1000, 32), 1000..1033);
+        assert_eq!(exposed(1000, 0, 32), 0..33);
+    }
+
+    #[test]
+    fn columns_are_queued_at_the_edges() {
+        TestBuffer::clear();
+        let mut scroll = Scroll::new(Mirroring::Vertical);
+        // column 33 is the second column of the right nametable
+        assert!(scroll.move_to::<TestBuffer, _, 128>(8, 0, &Stripes));
+        assert_eq!(segments(), [(0x80 | 30, 0x2401)]);
+        assert_eq!(unsafe { *TestBuffer::buffer().read(3) }, 33);
+
+        TestBuffer::clear();
+        assert!(scroll.move_to::<TestBuffer, _, 128>(0, 0, &Stripes));
+        assert_eq!(segments(), [(0x80 | 30, 0x2000)]);
+    }
+
+    #[test]
+    fn rows_are_queued_at_the_edges() {
+        TestBuffer::clear();
+        let mut scroll = Scroll::new(Mirroring::Horizontal);
+        // row 31 is the second row of the bottom nametable
+        assert!(scroll.move_to::<TestBuffer, _, 128>(0, 8, &Stripes));
+        assert_eq!(segments(), [(32, 0x2820)]);
+    }
+
+    #[test]
+    fn jumps_too_big_for_the_buffer_ask_for_a_redraw() {
+        TestBuffer::clear();
+        let mut scroll = Scroll::new(Mirroring::Vertical);
+        assert!(!scroll.move_to::<TestBuffer, _, 128>(4000, 0, &Stripes));
+        assert_eq!(TestBuffer::remaining(), 128);
+        assert_eq!(scroll.x(), 4000);
+    }
+
+    #[test]
+    fn apply_sets_nametable_and_fine_scroll() {
+        mock_bus::reset();
+        let mut scroll = Scroll::new(Mirroring::Vertical);
+        TestBuffer::clear();
+        scroll.move_to::<TestBuffer, _, 128>(300, 0, &Stripes);
+        unsafe {
+            ppu::write_ctrl(Ctrl::NMI);
+            scroll.apply();
+        }
+        mock_bus::with(|bus| {
+            assert_eq!(bus.ppu.ctrl, Ctrl::NMI.bits() | 1);
+            assert_eq!((bus.ppu.scroll_x, bus.ppu.scroll_y), (44, 0));
+        });
+
+        let mut scroll = Scroll::new(Mirroring::Horizontal);
+        TestBuffer::clear();
+        scroll.move_to::<TestBuffer, _, 128>(0, 250, &Stripes);
+        unsafe {
+            scroll.apply();
+        }
+        mock_bus::with(|bus| {
+            assert_eq!(bus.ppu.ctrl, Ctrl::NMI.bits() | 2);
+            assert_eq!((bus.ppu.scroll_x, bus.ppu.scroll_y), (0, 10));
+        });
+    }
+}
@@ -1,38 +1,363 @@
 use crate::addr::Addr;
+use crate::ppu::{self, Ctrl};
 use crate::vec2::Pos;
 
 const ADDR: Addr = Addr(0x200);
 const OAM_DMA: Addr = Addr(0x4014);
 const OAM_ADDR: Addr = Addr(0x2003);
 
+// any Y from $EF up is below the visible area
+const HIDDEN_Y: u8 = 0xFF;
+
+// Only 8 sprites show on a scanline, the ones in the lowest OAM slots. With
+// rotation on, the order of sprites changes every frame so the ones that
+// get dropped flicker rather than vanish. Pinned sprites (the player, say)
+// always go in the lowest slots and aren't rotated; add them first:
+//     sprites.set_rotation(true);
+//     // each frame
+//     sprites.clear();
+//     sprites.set_pinned(true);
+//     sprites.add_metasprite(x, y, &PLAYER, 0);
+//     sprites.set_pinned(false);
+//     // enemies, bullets..
+//     sprites.finish();
+//
+// `finish` is needed with or without rotation: it hides the slots that
+// weren't used this frame. Sprites past the 64th are dropped.
 pub struct SpriteState {
     index: isize,
+    // OAM slot 0 belongs to a sprite-0 split, see ppu::wait_sprite_zero
+    sprite_zero: bool,
+    rotation: bool,
+    pinned: bool,
+    // with rotation on, the lowest slot unpinned sprites have used
+    top: isize,
+    // unpinned sprites added this frame and last frame
+    placed: u8,
+    ring: u8,
+    // slots to rotate by, kept below `ring`
+    turn: u8,
+    // slots with Y already off screen, as byte offsets
+    hidden_start: isize,
+    hidden_end: isize,
 }
 impl Default for SpriteState {
     fn default() -> Self {
-        Self { index: 0 }
+        Self {
+            index: 0,
+            sprite_zero: false,
+            rotation: false,
+            pinned: false,
+            top: 256,
+            placed: 0,
+            ring: 0,
+            turn: 0,
+            // OAM starts out as garbage
+            hidden_start: 0,
+            hidden_end: 0,
+        }
     }
 }
 impl SpriteState {
+    // Start a new frame. Nothing is written until sprites are added; the
+    // old ones stay in OAM until overwritten or hidden by `finish`.
     pub fn clear(&mut self) {
-        for i in 0..256 {
-            *ADDR.offset(i) = 0;
+        self.index = self.first_slot();
+        self.top = 256;
+        self.placed = 0;
+    }
+    fn first_slot(&self) -> isize {
+        if self.sprite_zero {
+            4
+        } else {
+            0
         }
-        self.index = 0;
+    }
+    // Place the split marker in slot 0; `add` and `clear` leave it alone
+    // from now on. It needs to overlap an opaque background pixel.
+    pub fn reserve_sprite_zero(&mut self, x: u8, y: u8, tile: u8, attr: u8) {
+        *ADDR.offset(0) = y;
+        *ADDR.offset(1) = tile;
+        *ADDR.offset(2) = attr;
+        *ADDR.offset(3) = x;
+        self.sprite_zero = true;
+        self.hidden_start = self.hidden_start.max(4);
+        if self.index < 4 {
+            self.index = 4;
+        }
+    }
+    pub fn release_sprite_zero(&mut self) {
+        self.sprite_zero = false;
+    }
+    pub fn set_rotation(&mut self, on: bool) {
+        self.rotation = on;
+    }
+    // sprites added while pinned are never rotated out
+    pub fn set_pinned(&mut self, pinned: bool) {
+        self.pinned = pinned;
+    }
+    pub fn is_full(&self) -> bool {
+        self.index >= self.top
     }
     pub fn add(&mut self, x: u8, y: u8, tile: u8, attr: u8) {
         // attr is palette + flags
-        *ADDR.offset(self.index) = y;
-        *ADDR.offset(self.index + 1) = tile;
-        *ADDR.offset(self.index + 2) = attr;
-        *ADDR.offset(self.index + 3) = x;
-        self.index += 4;
+        let slot = if self.rotation && !self.pinned {
+            match self.rotated_slot() {
+                Some(slot) => slot,
+                None => return,
+            }
+        } else {
+            if self.is_full() {
+                return;
+            }
+            self.index += 4;
+            self.index - 4
+        };
+        *ADDR.offset(slot) = y;
+        *ADDR.offset(slot + 1) = tile;
+        *ADDR.offset(slot + 2) = attr;
+        *ADDR.offset(slot + 3) = x;
     }
     pub fn add_at_pos(&mut self, pos: &Pos, tile: u8, attr: u8) {
         self.add(pos.x, pos.y, tile, attr)
     }
+    // Unpinned sprites fill OAM downwards from slot 63, except that the
+    // first `ring` of them (as many as last frame) start `turn` slots in
+    // and wrap round, so with `n` sprites each gets its turn in the lowest
+    // slot within `n` frames without anything being moved.
+    fn rotated_slot(&mut self) -> Option<isize> {
+        if self.placed == 0 {
+            // keep clear of the pinned sprites
+            self.ring = self.ring.min(((256 - self.index) / 4) as u8);
+        }
+        let n = self.placed;
+        self.placed = n.saturating_add(1);
+        let step = if n < self.ring {
+            (n + self.turn) % self.ring
+        } else {
+            n
+        };
+        let slot = 252 - step as isize * 4;
+        if slot < self.index {
+            return None;
+        }
+        self.top = self.top.min(slot);
+        Some(slot)
+    }
+    // Call once all of a frame's sprites are added, before the DMA. Hides
+    // the free slots that aren't hidden already, and with rotation on,
+    // the gaps left among the unpinned sprites when there are fewer than
+    // last frame.
+    pub fn finish(&mut self) {
+        // the free slots are index..top
+        hide_slots(self.index, self.top.min(self.hidden_start));
+        hide_slots(self.index.max(self.hidden_end), self.top);
+        self.hidden_start = self.index;
+        self.hidden_end = self.top;
+
+        let mut n = self.placed;
+        while n < self.ring {
+            let slot = 252 - ((n + self.turn) % self.ring) as isize * 4;
+            if slot >= self.index {
+                *ADDR.offset(slot) = HIDDEN_Y;
+            }
+            n += 1;
+        }
+        self.ring = self.placed.min(64);
+        self.turn = if self.ring > 1 {
+            (self.turn + 1) % self.ring
+        } else {
+            0
+        };
+    }
+    // Draw a metasprite with its bounding box at (x, y). Each part's
+    // attributes are XORed with `attr`, so HFLIP/VFLIP mirror the whole
+    // thing (flipping each part's own flips) and palette and PRIORITY bits
+    // flip those of every part; parts with 0 there just take `attr`'s.
+    // Parts that would be off screen are skipped.
+    pub fn add_metasprite(&mut self, x: i16, y: i16, sprite: &Metasprite, attr: u8) {
+        let hflip = attr & HFLIP != 0;
+        let vflip = attr & VFLIP != 0;
+        for part in sprite.parts {
+            let mut dx = part.dx as i16;
+            let mut dy = part.dy as i16;
+            if hflip {
+                dx = sprite.left + sprite.right - dx - 8;
+            }
+            if vflip {
+                // the box is `bottom + height` tall and each part `height`,
+                // so the heights cancel out
+                dy = sprite.top + sprite.bottom - dy;
+            }
+            let px = x + dx - sprite.left;
+            let py = y + dy - sprite.top;
+            if !(0..=255).contains(&px) || !(0..240).contains(&py) {
+                continue;
+            }
+            self.add(px as u8, py as u8, part.tile, part.attr ^ attr);
+        }
+    }
+}
+
+// byte offsets start..end
+fn hide_slots(start: isize, end: isize) {
+    let mut slot = start;
+    while slot < end {
+        *ADDR.offset(slot) = HIDDEN_Y;
+        slot += 4;
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct SpritePart {
+    pub dx: i8,
+    pub dy: i8,
+    pub tile: u8,
+    pub attr: u8,
+}
+impl SpritePart {
+    pub const fn new(dx: i8, dy: i8, tile: u8, attr: u8) -> Self {
+        Self { dx, dy, tile, attr }
+    }
+}
+
+// A group of hardware sprites drawn together, usually a ROM constant:
+//     const PLAYER: Metasprite = Metasprite::new(&[
+//         SpritePart::new(0, 0, 0x10, 0),
+//         SpritePart::new(8, 0, 0x11, 0),
+//     ]);
+pub struct Metasprite {
+    pub parts: &'static [SpritePart],
+    // bounding box of the part offsets; the right edge includes the width
+    // of a sprite, the bottom edge doesn't include its height since that
+    // depends on the sprite size. i16 since `dx + 8` can pass i8::MAX.
+    left: i16,
+    right: i16,
+    top: i16,
+    bottom: i16,
+}
+impl Metasprite {
+    pub const fn new(parts: &'static [SpritePart]) -> Self {
+        let (mut left, mut right, mut top, mut bottom) = (i16::MAX, i16::MIN, i16::MAX, i16::MIN);
+        let mut i = 0;
+        while i < parts.len() {
+            let (dx, dy) = (parts[i].dx as i16, parts[i].dy as i16);
+            if dx < left {
+                left = dx;
+            }
+            if dx + 8 > right {
+                right = dx + 8;
+            }
+            if dy < top {
+                top = dy;
+            }
+            if dy > bottom {
+                bottom = dy;
+            }
+            i += 1;
+        }
+        Self {
+            parts,
+            left,
+            right,
+            top,
+            bottom,
+        }
+    }
+    // 0 for both with no parts
+    pub fn width(&self) -> u8 {
+        if self.parts.is_empty() {
+            return 0;
+        }
+        (self.right - self.left) as u8
+    }
+    pub fn height(&self) -> u8 {
+        if self.parts.is_empty() {
+            return 0;
+        }
+        (self.bottom - self.top) as u8 + size().height()
+    }
+    pub fn hitbox(&self, x: u8, y: u8) -> Hitbox {
+        Hitbox::new(x, y, self.width(), self.height())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum SpriteSize {
+    Small8x8,
+    Tall8x16,
+}
+impl SpriteSize {
+    pub const fn height(self) -> u8 {
+        match self {
+            SpriteSize::Small8x8 => 8,
+            SpriteSize::Tall8x16 => 16,
+        }
+    }
+}
+
+// PPUCTRL is shared with everything else, so set this at init or in vblank
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn set_size(size: SpriteSize) {
+    match size {
+        SpriteSize::Small8x8 => ppu::and_ctrl(!Ctrl::SPRITE_8X16),
+        SpriteSize::Tall8x16 => ppu::or_ctrl(Ctrl::SPRITE_8X16),
+    }
 }
 
+pub fn size() -> SpriteSize {
+    if ppu::ctrl().contains(Ctrl::SPRITE_8X16) {
+        SpriteSize::Tall8x16
+    } else {
+        SpriteSize::Small8x8
+    }
+}
+
+// In 8x16 mode the tile byte picks a pair of tiles: bit 0 selects the
+// pattern table ($0000 or $1000) and the rest is the top tile, which must
+// be even. The bottom half is the next tile. PPUCTRL's sprite table bit is
+// ignored in this mode.
+pub const fn tall_tile(top: u8, table_1000: bool) -> u8 {
+    (top & 0xFE) | table_1000 as u8
+}
+
+// Screen-space box for collisions, in pixels
+#[derive(Copy, Clone, PartialEq)]
+pub struct Hitbox {
+    pub x: u8,
+    pub y: u8,
+    pub width: u8,
+    pub height: u8,
+}
+impl Hitbox {
+    pub const fn new(x: u8, y: u8, width: u8, height: u8) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+    // one hardware sprite at the current sprite size
+    pub fn of_sprite(x: u8, y: u8) -> Self {
+        Self::new(x, y, 8, size().height())
+    }
+    // a `columns` x `rows` grid of hardware sprites
+    pub fn of_sprites(x: u8, y: u8, columns: u8, rows: u8) -> Self {
+        Self::new(x, y, columns * 8, rows * size().height())
+    }
+    pub fn contains(&self, x: u8, y: u8) -> bool {
+        x.wrapping_sub(self.x) < self.width && y.wrapping_sub(self.y) < self.height
+    }
+    pub fn overlaps(&self, other: &Hitbox) -> bool {
+        (self.x as u16) < other.x as u16 + other.width as u16
+            && (other.x as u16) < self.x as u16 + self.width as u16
+            && (self.y as u16) < other.y as u16 + other.height as u16
+            && (other.y as u16) < self.y as u16 + self.height as u16
+    }
+}
+
+#[allow(clippy::missing_safety_doc)]
 pub unsafe fn dma() {
     OAM_ADDR.write(0);
     OAM_DMA.write((ADDR.addr() >> 8) as u8);
@@ -44,3 +369,184 @@ pub const PRIORITY: u8 = 0b100000;
 pub const HFLIP: u8 = 0b1000000;
 #[allow(dead_code)]
 pub const VFLIP: u8 = 0b10000000;
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::mock_bus;
+
+    // the tiles of the 8 lowest slots on line `y`, the ones the PPU shows
+    fn shown(y: u8) -> [u8; 8] {
+        let mut tiles = [0xFF; 8];
+        mock_bus::with(|bus| {
+            let oam = bus.ram[0x200..0x300].chunks(4);
+            let on_line = oam.filter(|sprite| sprite[0] == y);
+            for (tile, sprite) in tiles.iter_mut().zip(on_line) {
+                *tile = sprite[1];
+            }
+        });
+        tiles
+    }
+
+    #[test]
+    fn rotation_shows_every_sprite_within_count_frames() {
+        mock_bus::reset();
+        let mut sprites = SpriteState::default();
+        sprites.set_rotation(true);
+        let mut seen = [false; 9];
+        for _ in 0..7 {
+            sprites.clear();
+            sprites.set_pinned(true);
+            sprites.add(0, 100, 0, 0);
+            sprites.add(8, 100, 1, 0);
+            sprites.set_pinned(false);
+            for tile in 2..9 {
+                sprites.add(tile * 8, 100, tile, 0);
+            }
+            sprites.finish();
+            let shown = shown(100);
+            assert_eq!(shown[..2], [0, 1]);
+            for tile in shown {
+                seen[tile as usize] = true;
+            }
+        }
+        assert_eq!(seen, [true; 9]);
+    }
+
+    #[test]
+    fn rotation_hides_gaps_when_there_are_fewer_sprites() {
+        mock_bus::reset();
+        let mut sprites = SpriteState::default();
+        sprites.set_rotation(true);
+        for count in [5, 5, 2] {
+            sprites.clear();
+            for tile in 0..count {
+                sprites.add(0, 100, tile, 0);
+            }
+            sprites.finish();
+        }
+        let mut shown = shown(100);
+        shown.sort();
+        assert_eq!(shown[..3], [0, 1, 0xFF]);
+        mock_bus::with(|bus| {
+            let on_screen = bus.ram[0x200..0x300]
+                .chunks(4)
+                .filter(|sprite| sprite[0] != HIDDEN_Y);
+            assert_eq!(on_screen.count(), 2);
+        });
+    }
+
+    #[test]
+    fn finish_hides_only_newly_freed_slots() {
+        mock_bus::reset();
+        let mut sprites = SpriteState::default();
+        for count in [5, 3] {
+            sprites.clear();
+            for tile in 0..count {
+                sprites.add(0, 100, tile, 0);
+            }
+            sprites.finish();
+            // already hidden, so the next finish shouldn't write it
+            mock_bus::with(|bus| bus.ram[0x200 + 10 * 4] = 50);
+        }
+        mock_bus::with(|bus| {
+            let y: std::vec::Vec<u8> = bus.ram[0x200..0x300].iter().step_by(4).copied().collect();
+            assert_eq!(y[..6], [100, 100, 100, HIDDEN_Y, HIDDEN_Y, HIDDEN_Y]);
+            assert_eq!(y[10], 50);
+            assert_eq!(y[63], HIDDEN_Y);
+        });
+    }
+
+    #[test]
+    fn sprites_past_64_are_dropped() {
+        mock_bus::reset();
+        let mut sprites = SpriteState::default();
+        sprites.clear();
+        for tile in 0..70 {
+            sprites.add(0, 100, tile, 0);
+        }
+        assert!(sprites.is_full());
+        sprites.finish();
+        mock_bus::with(|bus| {
+            let tiles: std::vec::Vec<u8> =
+                bus.ram[0x201..0x300].iter().step_by(4).copied().collect();
+            assert_eq!(tiles, (0..64).collect::<std::vec::Vec<u8>>());
+        });
+    }
+
+    #[test]
+    fn tall_tiles_pick_a_bank_with_bit_0() {
+        assert_eq!(tall_tile(0x20, false), 0x20);
+        assert_eq!(tall_tile(0x20, true), 0x21);
+        // the top tile is always the even one
+        assert_eq!(tall_tile(0x21, false), 0x20);
+        assert_eq!(tall_tile(0xFF, true), 0xFF);
+    }
+
+    #[test]
+    fn set_size_only_touches_its_bit() {
+        mock_bus::reset();
+        unsafe {
+            ppu::write_ctrl(Ctrl::NMI);
+            set_size(SpriteSize::Tall8x16);
+        }
+        mock_bus::with(|bus| assert_eq!(bus.ppu.ctrl, (Ctrl::NMI | Ctrl::SPRITE_8X16).bits()));
+        assert!(size() == SpriteSize::Tall8x16);
+        assert_eq!(Hitbox::of_sprite(0, 0).height, 16);
+        unsafe {
+            set_size(SpriteSize::Small8x8);
+        }
+        mock_bus::with(|bus| assert_eq!(bus.ppu.ctrl, Ctrl::NMI.bits()));
+        assert_eq!(Hitbox::of_sprites(0, 0, 2, 3).height, 24);
+    }
+
+    #[test]
+    fn hitboxes_touching_at_an_edge_dont_overlap() {
+        let hitbox = Hitbox::new(10, 20, 8, 8);
+        assert!(hitbox.overlaps(&Hitbox::new(17, 27, 8, 8)));
+        assert!(!hitbox.overlaps(&Hitbox::new(18, 20, 8, 8)));
+        assert!(!hitbox.overlaps(&Hitbox::new(10, 28, 8, 8)));
+        assert!(!hitbox.overlaps(&Hitbox::new(2, 20, 8, 8)));
+        assert!(hitbox.overlaps(&Hitbox::new(3, 13, 8, 8)));
+        // no wrapping at the right of the screen
+        let right = Hitbox::new(250, 0, 8, 8);
+        assert!(!right.overlaps(&Hitbox::new(0, 0, 8, 8)));
+        assert!(right.contains(255, 7));
+        assert!(!right.contains(2, 0));
+        assert!(!hitbox.contains(18, 20));
+        assert!(hitbox.contains(17, 27));
+    }
+
+    #[test]
+    fn metasprite_size() {
+        const PAIR: Metasprite =
+            Metasprite::new(&[SpritePart::new(-4, -8, 1, 0), SpritePart::new(4, 0, 2, 0)]);
+        const EMPTY: Metasprite = Metasprite::new(&[]);
+        // the right edge is past i8::MAX
+        const WIDE: Metasprite =
+            Metasprite::new(&[SpritePart::new(-8, 0, 1, 0), SpritePart::new(124, 0, 2, 0)]);
+        mock_bus::reset();
+        assert_eq!((PAIR.width(), PAIR.height()), (16, 16));
+        assert_eq!((WIDE.width(), WIDE.height()), (140, 8));
+        assert_eq!((EMPTY.width(), EMPTY.height()), (0, 0));
+    }
+
+    #[test]
+    fn metasprite_attributes_are_xored() {
+        const FLIPPED: Metasprite = Metasprite::new(&[
+            SpritePart::new(0, 0, 1, HFLIP | 1),
+            SpritePart::new(8, 0, 2, 0),
+        ]);
+        mock_bus::reset();
+        let mut sprites = SpriteState::default();
+        sprites.add_metasprite(100, 50, &FLIPPED, HFLIP | 2);
+        mock_bus::with(|bus| {
+            let oam = &bus.ram[0x200..0x208];
+            // mirrored: the right part is drawn on the left
+            assert_eq!(oam[..4], [50, 1, 3, 108]);
+            assert_eq!(oam[4..], [50, 2, HFLIP | 2, 100]);
+        });
+    }
+}
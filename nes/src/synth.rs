@@ -0,0 +1,577 @@
+// A software model of the pulse, triangle and noise channels that plays
+// back what the rest of the crate writes to the mock bus, so sounds can be
+// previewed and golden-tested without an emulator. Host only:
+//     let samples = synth::render_sfx(&sfx::COIN, Region::Ntsc);
+//     fs::write("coin.wav", synth::wav(&samples)).unwrap();
+//     // or in a test, against a file rendered earlier and listened to
+//     // (tests/synth.rs does this for the crate's own golden files)
+//     assert!(synth::wav(&samples) == include_bytes!("coin.wav"));
+//
+// For anything else, drive the crate as a game would and call
+// `Synth::run_frame` once per frame. It takes the register writes logged
+// since the last call and renders a frame of 44.1 kHz audio:
+//     let mut synth = Synth::new(Region::Ntsc);
+//     apu::init();
+//     apu.play_sfx(&sfx::COIN);
+//     for _ in 0..60 {
+//         apu.run_sfx();
+//         synth.run_frame();
+//     }
+//
+// Writes within a frame all take effect at its start. The DMC isn't
+// modelled and doesn't make a sound.
+extern crate std;
+
+use std::{mem, vec::Vec};
+
+use crate::{
+    apu::{self, SoundEffect, APU, LENGTHS},
+    mock_bus,
+    music::{Music, Song},
+    note::{self, Region},
+};
+
+pub const SAMPLE_RATE: u32 = 44_100;
+
+const DUTIES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+const TRIANGLE_STEPS: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+// in CPU cycles
+const NTSC_NOISE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_NOISE: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+// CPU cycles of each frame counter step. The 4th ends the sequence in
+// 4-step mode and does nothing in 5-step mode, which ends on the 5th.
+const NTSC_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_STEPS: [u32; 5] = [8313, 16627, 24939, 33252, 41565];
+
+#[derive(Default)]
+struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    // the constant volume, or the decay period
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+impl Envelope {
+    fn write(&mut self, value: u8) {
+        self.looping = value & 0x20 != 0;
+        self.constant = value & 0x10 != 0;
+        self.volume = value & 0x0F;
+    }
+
+    fn quarter_frame(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+#[derive(Default)]
+struct Pulse {
+    // pulse 1's sweep subtracts one more when going up
+    ones_complement: bool,
+    enabled: bool,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    length: u8,
+    envelope: Envelope,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+impl Pulse {
+    fn write(&mut self, reg: u16, value: u8) {
+        match reg {
+            0 => {
+                self.duty = value >> 6;
+                self.envelope.write(value);
+            }
+            1 => {
+                self.sweep_enabled = value & 0x80 != 0;
+                self.sweep_period = (value >> 4) & 7;
+                self.sweep_negate = value & 0x08 != 0;
+                self.sweep_shift = value & 7;
+                self.sweep_reload = true;
+            }
+            2 => self.period = self.period & 0x700 | value as u16,
+            _ => {
+                self.period = self.period & 0xFF | ((value & 7) as u16) << 8;
+                if self.enabled {
+                    self.length = LENGTHS[(value >> 3) as usize];
+                }
+                self.step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.period + change
+        } else if self.ones_complement {
+            self.period.saturating_sub(change + 1)
+        } else {
+            self.period.saturating_sub(change)
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.period < 8 || self.sweep_target() > 0x7FF
+    }
+
+    // every CPU cycle; the sequencer steps every 2 (t + 1)
+    fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = (self.period + 1) * 2 - 1;
+            self.step = (self.step + 1) & 7;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn half_frame(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+        if !self.envelope.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.muted() || DUTIES[self.duty as usize][self.step as usize] == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[derive(Default)]
+struct Triangle {
+    enabled: bool,
+    // also halts the length counter
+    control: bool,
+    reload_value: u8,
+    reload: bool,
+    linear: u8,
+    period: u16,
+    timer: u16,
+    step: u8,
+    length: u8,
+}
+impl Triangle {
+    fn write(&mut self, reg: u16, value: u8) {
+        match reg {
+            0 => {
+                self.control = value & 0x80 != 0;
+                self.reload_value = value & 0x7F;
+            }
+            1 => {}
+            2 => self.period = self.period & 0x700 | value as u16,
+            _ => {
+                self.period = self.period & 0xFF | ((value & 7) as u16) << 8;
+                if self.enabled {
+                    self.length = LENGTHS[(value >> 3) as usize];
+                }
+                self.reload = true;
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            // periods this short are ultrasonic; real hardware turns them
+            // into a buzz around the midpoint, this just stops
+            if self.length > 0 && self.linear > 0 && self.period >= 2 {
+                self.step = (self.step + 1) & 31;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn quarter_frame(&mut self) {
+        if self.reload {
+            self.linear = self.reload_value;
+        } else if self.linear > 0 {
+            self.linear -= 1;
+        }
+        if !self.control {
+            self.reload = false;
+        }
+    }
+
+    fn half_frame(&mut self) {
+        if !self.control && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        TRIANGLE_STEPS[self.step as usize]
+    }
+}
+
+struct Noise {
+    enabled: bool,
+    short: bool,
+    period: u16,
+    timer: u16,
+    shift: u16,
+    length: u8,
+    envelope: Envelope,
+}
+impl Noise {
+    fn write(&mut self, reg: u16, value: u8, periods: &[u16; 16]) {
+        match reg {
+            0 => self.envelope.write(value),
+            1 => {}
+            2 => {
+                self.short = value & 0x80 != 0;
+                self.period = periods[(value & 0x0F) as usize];
+            }
+            _ => {
+                if self.enabled {
+                    self.length = LENGTHS[(value >> 3) as usize];
+                }
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let tap = if self.short { 6 } else { 1 };
+            let feedback = (self.shift ^ self.shift >> tap) & 1;
+            self.shift = self.shift >> 1 | feedback << 14;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn half_frame(&mut self) {
+        if !self.envelope.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.shift & 1 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+pub struct Synth {
+    region: Region,
+    pulses: [Pulse; 2],
+    triangle: Triangle,
+    noise: Noise,
+    five_step: bool,
+    // CPU cycles into the frame counter sequence
+    sequence: u32,
+    // video frames are a whole number of CPU cycles plus a half
+    odd_frame: bool,
+    // CPU cycles into the current output sample, in 1/SAMPLE_RATE units
+    sample_time: u32,
+    mix: f32,
+    mixed: u32,
+    // high-pass filter state, as on the console's output
+    last_in: f32,
+    last_out: f32,
+    samples: Vec<i16>,
+}
+
+impl Synth {
+    pub fn new(region: Region) -> Self {
+        Self {
+            region,
+            pulses: [
+                Pulse {
+                    ones_complement: true,
+                    ..Pulse::default()
+                },
+                Pulse::default(),
+            ],
+            triangle: Triangle::default(),
+            noise: Noise {
+                enabled: false,
+                short: false,
+                period: NTSC_NOISE[0],
+                timer: 0,
+                shift: 1,
+                length: 0,
+                envelope: Envelope::default(),
+            },
+            five_step: false,
+            sequence: 0,
+            odd_frame: false,
+            sample_time: 0,
+            mix: 0.0,
+            mixed: 0,
+            last_in: 0.0,
+            last_out: 0.0,
+            samples: Vec::new(),
+        }
+    }
+
+    // apply a write to $4000-$4017
+    pub fn write(&mut self, addr: u16, value: u8) {
+        let reg = addr & 3;
+        match addr {
+            0x4000..=0x4003 => self.pulses[0].write(reg, value),
+            0x4004..=0x4007 => self.pulses[1].write(reg, value),
+            0x4008..=0x400B => self.triangle.write(reg, value),
+            0x400C..=0x400F => {
+                let periods = match self.region {
+                    Region::Ntsc => &NTSC_NOISE,
+                    Region::Pal => &PAL_NOISE,
+                };
+                self.noise.write(reg, value, periods)
+            }
+            0x4015 => {
+                self.pulses[0].enabled = value & 1 != 0;
+                self.pulses[1].enabled = value & 2 != 0;
+                self.triangle.enabled = value & 4 != 0;
+                self.noise.enabled = value & 8 != 0;
+                for pulse in &mut self.pulses {
+                    if !pulse.enabled {
+                        pulse.length = 0;
+                    }
+                }
+                if !self.triangle.enabled {
+                    self.triangle.length = 0;
+                }
+                if !self.noise.enabled {
+                    self.noise.length = 0;
+                }
+            }
+            0x4017 => {
+                self.five_step = value & 0x80 != 0;
+                self.sequence = 0;
+                if self.five_step {
+                    self.quarter_frame();
+                    self.half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Apply the register writes logged on the mock bus since the last
+    // call, then render one video frame of audio.
+    pub fn run_frame(&mut self) {
+        let writes = mock_bus::with(|bus| mem::take(&mut bus.apu.writes));
+        for (addr, value) in writes {
+            self.write(addr, value);
+        }
+        self.render_frame();
+    }
+
+    // one video frame: 29780.5 CPU cycles on NTSC, 33247.5 on PAL
+    fn render_frame(&mut self) {
+        let cycles_x2 = match self.region {
+            Region::Ntsc => 59561,
+            Region::Pal => 66495,
+        };
+        let cycles = (cycles_x2 + self.odd_frame as u32) / 2;
+        self.odd_frame = !self.odd_frame;
+        let clock = self.region.clock();
+        for _ in 0..cycles {
+            self.cycle();
+            self.sample_time += SAMPLE_RATE;
+            if self.sample_time >= clock {
+                self.sample_time -= clock;
+                self.push_sample();
+            }
+        }
+    }
+
+    fn cycle(&mut self) {
+        let steps = match self.region {
+            Region::Ntsc => &NTSC_STEPS,
+            Region::Pal => &PAL_STEPS,
+        };
+        self.sequence += 1;
+        let last = if self.five_step { steps[4] } else { steps[3] };
+        if self.sequence == steps[0] || self.sequence == steps[2] {
+            self.quarter_frame();
+        } else if self.sequence == steps[1] || self.sequence == last {
+            self.quarter_frame();
+            self.half_frame();
+        }
+        if self.sequence >= last {
+            self.sequence = 0;
+        }
+
+        for pulse in &mut self.pulses {
+            pulse.clock();
+        }
+        self.triangle.clock();
+        self.noise.clock();
+
+        // https://www.nesdev.org/wiki/APU_Mixer
+        let pulses = (self.pulses[0].output() + self.pulses[1].output()) as f32;
+        let pulse_out = if pulses == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulses + 100.0)
+        };
+        let tnd = self.triangle.output() as f32 / 8227.0 + self.noise.output() as f32 / 12241.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+        self.mix += pulse_out + tnd_out;
+        self.mixed += 1;
+    }
+
+    fn quarter_frame(&mut self) {
+        for pulse in &mut self.pulses {
+            pulse.envelope.quarter_frame();
+        }
+        self.triangle.quarter_frame();
+        self.noise.envelope.quarter_frame();
+    }
+
+    fn half_frame(&mut self) {
+        for pulse in &mut self.pulses {
+            pulse.half_frame();
+        }
+        self.triangle.half_frame();
+        self.noise.half_frame();
+    }
+
+    // average the cycles since the last sample and take out DC
+    fn push_sample(&mut self) {
+        let input = self.mix / self.mixed.max(1) as f32;
+        self.mix = 0.0;
+        self.mixed = 0;
+        // 90 Hz high-pass
+        const ALPHA: f32 = 0.987_342;
+        let output = ALPHA * (self.last_out + input - self.last_in);
+        self.last_in = input;
+        self.last_out = output;
+        self.samples
+            .push((output * 32767.0).clamp(-32768.0, 32767.0) as i16);
+    }
+
+    // everything rendered so far, 44.1 kHz mono
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    pub fn into_samples(self) -> Vec<i16> {
+        self.samples
+    }
+}
+
+// Play `sfx` from a freshly initialised APU until it ends, plus a frame.
+// Uses this thread's mock bus, and sets `note::region()` to `region`.
+pub fn render_sfx(sfx: &'static SoundEffect, region: Region) -> Vec<i16> {
+    let mut synth = start(region);
+    let mut apu = APU::default();
+    apu.play_sfx(sfx);
+    while apu.is_playing() {
+        apu.run_sfx();
+        synth.run_frame();
+    }
+    synth.run_frame();
+    synth.into_samples()
+}
+
+// The first `frames` frames of `song`, as `render_sfx`.
+pub fn render_song(song: &'static Song, frames: u32, region: Region) -> Vec<i16> {
+    let mut synth = start(region);
+    let mut music = Music::new();
+    music.play(song);
+    for _ in 0..frames {
+        music.tick();
+        synth.run_frame();
+    }
+    synth.into_samples()
+}
+
+fn start(region: Region) -> Synth {
+    note::set_region(region);
+    mock_bus::with(|bus| bus.apu.writes.clear());
+    apu::init();
+    Synth::new(region)
+}
+
+// a 16-bit mono WAV file
+pub fn wav(samples: &[i16]) -> Vec<u8> {
+    let data = samples.len() as u32 * 2;
+    let mut out = Vec::with_capacity(44 + data as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, 1 channel
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    out.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    // bytes per frame, bits per sample
+    out.extend_from_slice(&2u16.to_le_bytes());
+    out.extend_from_slice(&16u16.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data.to_le_bytes());
+    for sample in samples {
+        out.extend_from_slice(&sample.to_le_bytes());
+    }
+    out
+}
@@ -0,0 +1,545 @@
+// Bordered dialogue window with word wrap, paging on A and a typewriter
+// effect. Everything after `draw_frame` goes through the ppu buffer, and
+// no frame queues more than the buffer has room for, so it can run
+// alongside the rest of a game's drawing.
+//
+//     static mut DIALOG: TextBox = TextBox::new(2, 20, 28, 6, &BOX_TILES).with_speed(2);
+//     DIALOG.open("IT'S DANGEROUS TO GO ALONE! TAKE THIS.");
+//     // once per frame, after Buffer::clear()
+//     match DIALOG.update::<Buffer, BUFFER_SIZE>(io::controller_buttons()) { .. }
+use core::iter;
+
+use crate::{
+    addr::Addr,
+    charset::{self, Charset},
+    io,
+    ppu::{self, Ctrl},
+    ppu_buffer::BufferTrait,
+};
+
+pub struct BoxTiles {
+    pub top_left: u8,
+    pub top_right: u8,
+    pub bottom_left: u8,
+    pub bottom_right: u8,
+    pub horizontal: u8,
+    pub vertical: u8,
+    // inside of the window
+    pub fill: u8,
+}
+
+impl BoxTiles {
+    // (left, middle, right) tiles of one row of a `height` tall box
+    fn row(&self, row: u8, height: u8) -> (u8, u8, u8) {
+        if row == 0 {
+            (self.top_left, self.horizontal, self.top_right)
+        } else if row == height - 1 {
+            (self.bottom_left, self.horizontal, self.bottom_right)
+        } else {
+            (self.vertical, self.fill, self.vertical)
+        }
+    }
+}
+
+fn tile_addr(nametable: u16, x: u8, y: u8) -> u16 {
+    nametable + x as u16 + y as u16 * 0x20
+}
+
+// Draw just the border of a `w` x `h` box at tile (x, y) on `nametable`
+// ($2000, $2400, ..), leaving the inside alone. Rendering must be off.
+// The box is at least 2x2, corners only.
+#[inline(never)]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn draw_frame(tiles: &BoxTiles, nametable: u16, x: u8, y: u8, w: u8, h: u8) {
+    assert!(w >= 2 && h >= 2, "text_box: a frame is at least 2x2 tiles");
+    let offset = tile_addr(nametable, x, y);
+    // -
+    ppu::write_addr(offset);
+    ppu::write_data(tiles.top_left);
+    for _ in 0..w - 2 {
+        ppu::write_data(tiles.horizontal);
+    }
+    ppu::write_data(tiles.top_right);
+    // |
+    ppu::write_addr(offset + 0x20);
+    ppu::or_ctrl(Ctrl::VRAM_INC_32);
+    for _ in 0..h - 2 {
+        ppu::write_data(tiles.vertical);
+    }
+    ppu::write_data(tiles.bottom_left);
+    // |
+    ppu::write_addr(offset + 0x20 + w as u16 - 1);
+    for _ in 0..h - 2 {
+        ppu::write_data(tiles.vertical);
+    }
+    ppu::write_data(tiles.bottom_right);
+    ppu::and_ctrl(!Ctrl::VRAM_INC_32);
+    // _
+    ppu::write_addr(offset + ((h as u16 - 1) * 0x20) + 1);
+    for _ in 0..w - 2 {
+        ppu::write_data(tiles.horizontal);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Status {
+    // drawing the window or text
+    Busy,
+    // page or message finished, waiting for A
+    Waiting,
+    // closed, or the last page was dismissed
+    Done,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Closed,
+    // queueing the window one row per frame
+    Opening(u8),
+    Typing,
+    PageFull,
+    // blanking the inside one row per frame before the next page
+    Clearing(u8),
+    End,
+}
+
+enum Step {
+    Char(u8),
+    NewLine,
+    PageFull,
+    End,
+}
+
+pub struct TextBox {
+    nametable: u16,
+    x: u8,
+    y: u8,
+    width: u8,
+    height: u8,
+    tiles: &'static BoxTiles,
+    charset: &'static Charset,
+    // characters revealed per frame
+    speed: u8,
+    text: &'static str,
+    // next byte of `text` to show
+    pos: usize,
+    line: u8,
+    column: u8,
+    // the current line was started by word wrap rather than '\n'
+    wrapped: bool,
+    state: State,
+    a_held: bool,
+}
+
+impl TextBox {
+    // position and size in tiles, border included, so at least 3x3 to
+    // leave room for text
+    pub const fn new(x: u8, y: u8, width: u8, height: u8, tiles: &'static BoxTiles) -> Self {
+        if width < 3 || height < 3 {
+            panic!("text_box: a box is at least 3x3 tiles");
+        }
+        Self {
+            nametable: 0x2000,
+            x,
+            y,
+            width,
+            height,
+            tiles,
+            charset: &charset::ASCII,
+            speed: 1,
+            text: "",
+            pos: 0,
+            line: 0,
+            column: 0,
+            wrapped: false,
+            state: State::Closed,
+            a_held: false,
+        }
+    }
+
+    // 0 is treated as 1
+    pub const fn with_speed(mut self, chars_per_frame: u8) -> Self {
+        self.speed = if chars_per_frame == 0 {
+            1
+        } else {
+            chars_per_frame
+        };
+        self
+    }
+
+    pub const fn with_charset(mut self, charset: &'static Charset) -> Self {
+        self.charset = charset;
+        self
+    }
+
+    pub const fn with_nametable(mut self, nametable: u16) -> Self {
+        self.nametable = nametable;
+        self
+    }
+
+    // The box's border, drawn straight to its nametable, for screens that
+    // keep it up between messages. Rendering must be off.
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe fn draw_frame(&self) {
+        draw_frame(
+            self.tiles,
+            self.nametable,
+            self.x,
+            self.y,
+            self.width,
+            self.height,
+        );
+    }
+
+    fn inner_width(&self) -> u8 {
+        self.width - 2
+    }
+
+    fn inner_height(&self) -> u8 {
+        self.height - 2
+    }
+
+    // Start showing `text`, drawing the window first. '\n' forces a line
+    // break; otherwise lines break between words.
+    pub fn open(&mut self, text: &'static str) {
+        self.text = text;
+        self.pos = 0;
+        self.line = 0;
+        self.column = 0;
+        self.wrapped = false;
+        self.state = State::Opening(0);
+    }
+
+    // Stop updating; the game is responsible for redrawing what was behind.
+    pub fn close(&mut self) {
+        self.state = State::Closed;
+    }
+
+    // `N` must fit a row of the box, 3 + width bytes, or it could never
+    // finish opening.
+    pub fn update<B: BufferTrait<N>, const N: usize>(&mut self, buttons: u8) -> Status {
+        assert!(
+            N >= 3 + self.width as usize,
+            "text_box: ppu buffer is too small for a row of the box"
+        );
+        let a = buttons & io::A != 0;
+        let pressed = a && !self.a_held;
+        self.a_held = a;
+
+        match self.state {
+            State::Closed => return Status::Done,
+            State::Opening(row) => {
+                if self.queue_row::<B, N>(row, row) {
+                    self.state = if row + 1 == self.height {
+                        State::Typing
+                    } else {
+                        State::Opening(row + 1)
+                    };
+                }
+            }
+            State::Typing => self.type_chars::<B, N>(),
+            State::PageFull => {
+                if pressed {
+                    self.state = State::Clearing(0);
+                }
+            }
+            State::Clearing(line) => {
+                // any middle row of the box is border + fill
+                if self.queue_row::<B, N>(line + 1, 1) {
+                    self.state = if line + 1 == self.inner_height() {
+                        self.line = 0;
+                        self.column = 0;
+                        State::Typing
+                    } else {
+                        State::Clearing(line + 1)
+                    };
+                }
+            }
+            State::End => {
+                if pressed {
+                    self.state = State::Closed;
+                    return Status::Done;
+                }
+            }
+        }
+
+        match self.state {
+            State::PageFull | State::End => Status::Waiting,
+            State::Closed => Status::Done,
+            _ => Status::Busy,
+        }
+    }
+
+    // queue row `row` of the window using the tiles of row `style`;
+    // false if the buffer is too full this frame
+    fn queue_row<B: BufferTrait<N>, const N: usize>(&self, row: u8, style: u8) -> bool {
+        if B::remaining() < 3 + self.width as usize {
+            return false;
+        }
+        let (left, middle, right) = self.tiles.row(style, self.height);
+        let tiles = iter::once(left)
+            .chain((0..self.inner_width()).map(|_| middle))
+            .chain(iter::once(right));
+        B::tiles(Addr(tile_addr(self.nametable, self.x, self.y + row)), tiles);
+        true
+    }
+
+    fn type_chars<B: BufferTrait<N>, const N: usize>(&mut self) {
+        let mut budget = self.speed;
+        while budget > 0 {
+            // a segment costs 3 bytes of header plus one per tile
+            let room = B::remaining().saturating_sub(3);
+            if room == 0 {
+                return;
+            }
+            let mut run = [0u8; 32];
+            let mut n = 0;
+            let (line, column) = (self.line, self.column);
+            let mut stop = false;
+            while budget > 0 && n < room.min(run.len()) {
+                match self.step() {
+                    Step::Char(tile) => {
+                        run[n] = tile;
+                        n += 1;
+                        budget -= 1;
+                    }
+                    Step::NewLine => break,
+                    Step::PageFull => {
+                        self.state = State::PageFull;
+                        stop = true;
+                        break;
+                    }
+                    Step::End => {
+                        self.state = State::End;
+                        stop = true;
+                        break;
+                    }
+                }
+            }
+            if n > 0 {
+                let addr = tile_addr(self.nametable, self.x + 1 + column, self.y + 1 + line);
+                B::tiles(Addr(addr), run[..n].iter().copied());
+            }
+            if stop || n == room {
+                return;
+            }
+        }
+    }
+
+    fn new_line(&mut self, wrapped: bool) -> Step {
+        self.line += 1;
+        self.column = 0;
+        self.wrapped = wrapped;
+        Step::NewLine
+    }
+
+    // move the cursor on by one character
+    fn step(&mut self) -> Step {
+        let bytes = self.text.as_bytes();
+        loop {
+            if self.pos >= bytes.len() {
+                return Step::End;
+            }
+            if self.line >= self.inner_height() {
+                return Step::PageFull;
+            }
+            let byte = bytes[self.pos];
+            if byte == b'\n' {
+                self.pos += 1;
+                return self.new_line(false);
+            }
+            if byte == b' ' && self.column == 0 && self.wrapped {
+                // don't start a wrapped line with the space we broke at
+                self.pos += 1;
+                continue;
+            }
+            if self.column >= self.inner_width() {
+                return self.new_line(true);
+            }
+            let word_start = self.pos == 0 || matches!(bytes[self.pos - 1], b' ' | b'\n');
+            if byte != b' ' && word_start && self.column > 0 {
+                let len = bytes[self.pos..]
+                    .iter()
+                    .take_while(|b| !matches!(b, b' ' | b'\n'))
+                    .count();
+                if self.column as usize + len > self.inner_width() as usize {
+                    return self.new_line(true);
+                }
+            }
+            self.pos += 1;
+            self.column += 1;
+            self.wrapped = false;
+            return Step::Char(self.charset.tile_or_blank(byte as char));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::{boxed::Box, thread_local};
+
+    use super::*;
+    use crate::{mock_bus, ppu_buffer::Buffer};
+
+    static TILES: BoxTiles = BoxTiles {
+        top_left: 1,
+        top_right: 2,
+        bottom_left: 3,
+        bottom_right: 4,
+        horizontal: 5,
+        vertical: 6,
+        fill: 7,
+    };
+
+    struct Small;
+    struct Tiny;
+
+    thread_local! {
+        static SMALL: *mut Buffer<32> = Box::into_raw(Box::new(Buffer::new()));
+        static TINY: *mut Buffer<8> = Box::into_raw(Box::new(Buffer::new()));
+    }
+
+    impl BufferTrait<32> for Small {
+        unsafe fn buffer() -> &'static mut Buffer<32> {
+            SMALL.with(|buffer| &mut **buffer)
+        }
+    }
+
+    impl BufferTrait<8> for Tiny {
+        unsafe fn buffer() -> &'static mut Buffer<8> {
+            TINY.with(|buffer| &mut **buffer)
+        }
+    }
+
+    #[test]
+    fn draw_frame_uses_the_box_nametable() {
+        mock_bus::reset();
+        let dialog = TextBox::new(1, 2, 4, 3, &TILES).with_nametable(0x2400);
+        mock_bus::with(|bus| bus.ppu.mirroring = ppu::Mirroring::FourScreen);
+        unsafe {
+            dialog.draw_frame();
+        }
+        mock_bus::with(|bus| {
+            let row = |y: u16| -> [u8; 4] {
+                core::array::from_fn(|x| bus.ppu.peek(0x2400 + y * 0x20 + 1 + x as u16))
+            };
+            assert_eq!(row(2), [1, 5, 5, 2]);
+            assert_eq!(row(3), [6, 0, 0, 6]);
+            assert_eq!(row(4), [3, 5, 5, 4]);
+            assert_eq!(bus.ppu.peek(0x2041), 0);
+        });
+    }
+
+    #[test]
+    fn speed_0_still_types() {
+        mock_bus::reset();
+        let mut dialog = TextBox::new(0, 0, 6, 3, &TILES).with_speed(0);
+        dialog.open("HI");
+        let mut status = Status::Busy;
+        for _ in 0..10 {
+            Small::clear();
+            status = dialog.update::<Small, 32>(0);
+        }
+        assert!(status == Status::Waiting);
+    }
+
+    // run a frame and draw what it queued
+    fn frame(dialog: &mut TextBox, buttons: u8) -> Status {
+        Small::clear();
+        let status = dialog.update::<Small, 32>(buttons);
+        unsafe {
+            Small::render();
+        }
+        status
+    }
+
+    // what row `y` of the nametable says from column `x` on, with the fill
+    // tile as '.'
+    fn text_at(x: u16, y: u16, len: u16) -> std::string::String {
+        mock_bus::with(|bus| {
+            (0..len)
+                .map(|i| {
+                    let tile = bus.ppu.peek(0x2000 + y * 0x20 + x + i);
+                    if tile == TILES.fill {
+                        '.'
+                    } else {
+                        (b' ' + tile) as char
+                    }
+                })
+                .collect()
+        })
+    }
+
+    #[test]
+    fn wraps_words_and_waits_for_a_between_pages() {
+        mock_bus::reset();
+        // 6x2 inside
+        let mut dialog = TextBox::new(0, 0, 8, 4, &TILES).with_speed(4);
+        dialog.open("HELLO THERE WORLD");
+        let mut frames = 0;
+        while frame(&mut dialog, 0) == Status::Busy {
+            frames += 1;
+            assert!(frames < 20);
+        }
+        // "THERE" doesn't fit after "HELLO ", and "WORLD" is for page two
+        assert_eq!(text_at(1, 1, 6), "HELLO ");
+        assert_eq!(text_at(1, 2, 6), "THERE ");
+        // nothing happens until A
+        assert!(frame(&mut dialog, 0) == Status::Waiting);
+        assert!(frame(&mut dialog, io::A) == Status::Busy);
+        while frame(&mut dialog, io::A) == Status::Busy {}
+        assert_eq!(text_at(1, 1, 6), "WORLD.");
+        assert_eq!(text_at(1, 2, 6), "......");
+        // A has to be let go and pressed again
+        assert!(frame(&mut dialog, io::A) == Status::Waiting);
+        assert!(frame(&mut dialog, 0) == Status::Waiting);
+        assert!(frame(&mut dialog, io::A) == Status::Done);
+    }
+
+    #[test]
+    fn typing_stays_within_the_buffer() {
+        mock_bus::reset();
+        let mut dialog = TextBox::new(0, 0, 22, 3, &TILES).with_speed(30);
+        dialog.open("ABCDEFGHIJKLMNOPQRST");
+        for _ in 0..3 {
+            frame(&mut dialog, 0);
+        }
+        // the game already queued 20 bytes this frame, leaving room for 9
+        // characters after a segment header
+        Small::clear();
+        Small::tiles(Addr(0x2300), iter::repeat_n(0, 17));
+        assert!(dialog.update::<Small, 32>(0) == Status::Busy);
+        assert_eq!(Small::remaining(), 0);
+        unsafe {
+            Small::render();
+        }
+        assert_eq!(text_at(1, 1, 10), "ABCDEFGHI.");
+        frame(&mut dialog, 0);
+        assert_eq!(text_at(1, 1, 20), "ABCDEFGHIJKLMNOPQRST");
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 3x3")]
+    fn boxes_need_room_for_text() {
+        TextBox::new(0, 0, 2, 5, &TILES);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 2x2")]
+    fn frames_need_corners() {
+        mock_bus::reset();
+        unsafe {
+            draw_frame(&TILES, 0x2000, 0, 0, 1, 4);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "too small")]
+    fn buffer_must_fit_a_row() {
+        let mut dialog = TextBox::new(0, 0, 6, 3, &TILES);
+        dialog.open("HI");
+        dialog.update::<Tiny, 8>(0);
+    }
+}
@@ -4,6 +4,7 @@ pub fn inc_u8(x: u8, dx: i8) -> u8 {
     ((x as i8) + dx) as u8
 }
 
+#[allow(clippy::missing_safety_doc)]
 pub unsafe fn debug_value(at: u16, value: u8) {
     Addr(at).write(0xaa);
     Addr(at + 1).write(value);
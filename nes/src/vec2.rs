@@ -23,6 +23,7 @@ impl Pos {
         self.x = inc_u8(self.x, delta.x);
         self.y = inc_u8(self.y, delta.y);
     }
+    #[allow(clippy::clone_on_copy)]
     pub fn shifted(&self, delta: &DPos) -> Pos {
         let mut new_pos = self.clone();
         new_pos.inc(delta);
@@ -84,6 +85,7 @@ impl DPos {
             },
         }
     }
+    #[allow(clippy::cast_abs_to_unsigned)]
     pub fn l1_norm(&self) -> u8 {
         (self.x).abs() as u8 + (self.y).abs() as u8
     }
@@ -0,0 +1,169 @@
+// Golden tests for the APU driver: sounds are rendered with `nes::synth`
+// and compared byte for byte with WAV files in tests/golden. After an
+// intended change, listen to the `.actual.wav` files a failure leaves in
+// the target directory, then re-record with
+//     UPDATE_GOLDEN=1 cargo test -p nes --test synth
+use std::{env, fs, path::PathBuf, thread};
+
+use nes::{
+    apu::{Channel, SfxStep, SoundEffect},
+    music::{Envelope, Event, Instrument, Pattern, Song, Track, NO_LOOP},
+    note::{Note, Region},
+    synth,
+};
+
+const COIN: SoundEffect = SoundEffect::new(
+    Channel::Pulse1,
+    &[
+        SfxStep::note(4, Note::parse("A3"), 0b10111111),
+        SfxStep::note(4, Note::parse("C#4"), 0b10111111),
+        SfxStep::note(4, Note::parse("F4"), 0b10111111),
+    ],
+);
+
+// period steps, only the last of which changes the high byte
+const SLIDE: SoundEffect = SoundEffect::new(
+    Channel::Pulse2,
+    &[
+        SfxStep::new(3, 0x1A0, 0b01111100),
+        SfxStep::new(3, 0x180, 0b01111010),
+        SfxStep::new(3, 0x140, 0b01111000),
+        SfxStep::new(3, 0x0F0, 0b01110110),
+    ],
+);
+
+const BASS: SoundEffect = SoundEffect::new(
+    Channel::Triangle,
+    &[
+        SfxStep::note(6, Note::parse("E2"), 1),
+        SfxStep::note(6, Note::parse("B1"), 1),
+    ],
+);
+
+const HIT: SoundEffect = SoundEffect::new(
+    Channel::Noise,
+    &[
+        SfxStep::new(2, 0x03, 0b0011_1111),
+        SfxStep::new(4, 0x08, 0b0011_1000),
+    ],
+);
+
+const LEAD: Instrument = Instrument::new(2, Envelope::new(&[15, 13, 11, 10, 9], NO_LOOP));
+const CHORD: Instrument = Instrument::new(1, Envelope::new(&[10, 8, 6, 4, 2, 0], NO_LOOP));
+const DRUM: Instrument = Instrument::new(0, Envelope::new(&[15, 10, 5, 0], NO_LOOP));
+
+const MELODY: &Pattern = &[
+    Event::Instrument(&LEAD),
+    Event::Vibrato(24, 4),
+    Event::Note(Note::parse("E4"), 2),
+    Event::Note(Note::parse("G4"), 2),
+    Event::Slide(-2),
+    Event::Note(Note::parse("B4"), 4),
+    Event::Rest(2),
+];
+const CHORDS: &Pattern = &[
+    Event::Instrument(&CHORD),
+    Event::Arpeggio(4, 7),
+    Event::Note(Note::parse("E3"), 4),
+    Event::Volume(8),
+    Event::Note(Note::parse("C3"), 6),
+];
+const BASS_LINE: &Pattern = &[
+    Event::Note(Note::parse("E2"), 3),
+    Event::Note(Note::parse("B1"), 3),
+    Event::Rest(4),
+];
+const BEAT: &Pattern = &[
+    Event::Instrument(&DRUM),
+    Event::Note(Note(12), 2),
+    Event::Note(Note(6), 2),
+];
+
+const SONG: Song = Song {
+    speed: 5,
+    tracks: [
+        Track::new(&[MELODY], Some(0)),
+        Track::new(&[CHORDS], Some(0)),
+        Track::new(&[BASS_LINE], Some(0)),
+        Track::new(&[BEAT], Some(0)),
+    ],
+};
+
+fn check(name: &str, samples: &[i16]) {
+    let wav = synth::wav(samples);
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.wav"));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, &wav).unwrap();
+        return;
+    }
+    let expected = fs::read(&path).unwrap_or_else(|_| {
+        panic!(
+            "{}: missing, record it with UPDATE_GOLDEN=1",
+            path.display()
+        )
+    });
+    if wav != expected {
+        let actual = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.actual.wav"));
+        fs::write(&actual, &wav).unwrap();
+        panic!(
+            "{name}: differs from {}, compare with {}",
+            path.display(),
+            actual.display()
+        );
+    }
+}
+
+#[test]
+fn pulse_notes() {
+    check("coin_ntsc", &synth::render_sfx(&COIN, Region::Ntsc));
+    check("coin_pal", &synth::render_sfx(&COIN, Region::Pal));
+}
+
+#[test]
+fn pulse_period_slide() {
+    check("slide", &synth::render_sfx(&SLIDE, Region::Ntsc));
+}
+
+#[test]
+fn triangle() {
+    check("bass_ntsc", &synth::render_sfx(&BASS, Region::Ntsc));
+    check("bass_pal", &synth::render_sfx(&BASS, Region::Pal));
+}
+
+#[test]
+fn noise() {
+    check("hit", &synth::render_sfx(&HIT, Region::Ntsc));
+}
+
+#[test]
+fn song() {
+    check("song_ntsc", &synth::render_song(&SONG, 60, Region::Ntsc));
+    check("song_pal", &synth::render_song(&SONG, 60, Region::Pal));
+}
+
+// every thread has its own mock bus and APU shadows, so renders running
+// side by side in different regions don't see each other's writes
+#[test]
+fn parallel_renders_match() {
+    let ntsc = synth::render_song(&SONG, 30, Region::Ntsc);
+    let pal = synth::render_song(&SONG, 30, Region::Pal);
+    let threads: Vec<_> = (0..8)
+        .map(|i| {
+            thread::spawn(move || {
+                let region = if i % 2 == 0 {
+                    Region::Ntsc
+                } else {
+                    Region::Pal
+                };
+                (region, synth::render_song(&SONG, 30, region))
+            })
+        })
+        .collect();
+    for thread in threads {
+        let (region, samples) = thread.join().unwrap();
+        let expected = if region == Region::Ntsc { &ntsc } else { &pal };
+        assert!(&samples == expected);
+    }
+}
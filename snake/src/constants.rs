@@ -1,4 +1,4 @@
-use nes::constants::ROW;
+use nes::{charset, constants::ROW};
 
 pub const N_ROWS: u8 = 28; // 28 fills screen
 pub const GRID_SIZE: u16 = (ROW as u16) * (N_ROWS as u16);
@@ -15,5 +15,7 @@ pub const HEART_SPRITE: u8 = 0x63;
 pub const WALL_SPRITE: u8 = 0x60;
 pub const COIN_SPRITE: u8 = HEART_SPRITE + 7;
 pub const AT_SPRITE: u8 = HEART_SPRITE - 4;
+pub const SEGMENT_SPRITE: u8 = charset::ASCII.tile('o');
+pub const FOOD_SPRITE: u8 = charset::ASCII.tile('*');
 
 pub const DT: u8 = 1;
@@ -1,7 +1,7 @@
 use core::ptr::addr_of_mut;
 use nes::{
     addr::Addr,
-    apu::{self, Sfx},
+    apu,
     capped_vec::CappedVec,
     io, ppu,
     ppu_buffer::{self, BufferTrait},
@@ -10,10 +10,10 @@ use nes::{
 };
 
 use crate::{
-    constants::{DT, GRID_SIZE, PLAYER_SPEED, PLAYER_WIDTH, WIDTH, HEIGHT, ORIGIN},
+    constants::{DT, GRID_SIZE, PLAYER_SPEED, PLAYER_WIDTH, WIDTH, HEIGHT, ORIGIN, SEGMENT_SPRITE, FOOD_SPRITE},
     level::{draw_level, get_tile_at, make_level, Tile},
     rng::{get_seeds, Rng},
-    Buffer,
+    sfx, Buffer,
 };
 
 const SEEDS: [u16; GRID_SIZE as usize] = get_seeds();
@@ -109,7 +109,7 @@ impl SnakeGame {
         if self.snake.segments.read(0).pos == self.food {
             self.grow_snake();
             self.food = random_pos(&mut self.rng);
-            apu.play_sfx(Sfx::LevelUp);
+            apu.play_sfx(&sfx::FOOD);
         }
     }
 
@@ -147,14 +147,14 @@ impl SnakeGame {
 
         if head.x < 0 || head.x >= WIDTH as i8 || head.y < 0 || head.y >= HEIGHT as i8 {
             self.alive = false;
-            apu.play_sfx(Sfx::Topout);
+            apu.play_sfx(&sfx::DEATH);
             return;
         }
 
         for segment in self.snake.segments.iter().skip(1) {
             if head == segment.pos {
                 self.alive = false;
-                apu.play_sfx(Sfx::Topout);
+                apu.play_sfx(&sfx::DEATH);
                 return;
             }
         }
@@ -167,9 +167,11 @@ impl SnakeGame {
 
     fn draw(&self, sprites: &mut SpriteState) {
         for segment in self.snake.segments.iter() {
-            sprites.add_at_pos(&OtherPos { x: segment.pos.x as u8, y: segment.pos.y as u8 }, 'o' as u8 - 32, 0);
+            sprites.add_at_pos(&OtherPos { x: segment.pos.x as u8, y: segment.pos.y as u8 }, SEGMENT_SPRITE, 0);
         }
-        sprites.add_at_pos(&OtherPos { x: self.food.x as u8, y: self.food.y as u8 }, '*' as u8 - 32, 1);
+        sprites.set_pinned(true);
+        sprites.add_at_pos(&OtherPos { x: self.food.x as u8, y: self.food.y as u8 }, FOOD_SPRITE, 1);
+        sprites.set_pinned(false);
     }
 }
 
@@ -10,7 +10,7 @@ use nes::addr::Addr;
 use nes::capped_vec::CappedVec;
 use nes::ppu_buffer::BufferTrait;
 use nes::sprites::SpriteState;
-use nes::{apu, io, ppu, ppu_buffer, sprites};
+use nes::{apu, charset, io, ppu, ppu_buffer, sprites};
 use rng::Rng;
 use utils::u8_to_decimal;
 
@@ -18,6 +18,7 @@ mod constants;
 mod game;
 mod level;
 mod rng;
+mod sfx;
 mod utils;
 
 const BUFFER_SIZE: usize = 40;
@@ -34,6 +35,8 @@ fn _main(_argc: isize, _argv: *const *const u8) -> isize {
     apu::init();
     let mut apu = apu::APU::default();
     let mut sprites = SpriteState::default();
+    // a long snake puts more than 8 segments on a line
+    sprites.set_rotation(true);
     let mut game = None;
     SnakeGame::new(&mut game);
 
@@ -51,6 +54,7 @@ fn _main(_argc: isize, _argv: *const *const u8) -> isize {
         sprites.clear();
         apu.run_sfx();
         game::frame(game.as_mut().unwrap(), &mut apu, &mut sprites);
+        sprites.finish();
     }
 }
 
@@ -139,7 +143,7 @@ fn draw_digits(x: u8) {
     for d in u8_to_decimal(x)
         .into_iter()
         .rev()
-        .map(|d| io::digit_to_ascii(d) - 32)
+        .map(|d| charset::ASCII.digit(d))
     {
         unsafe {
             ppu::write_data(d);
@@ -0,0 +1,29 @@
+use nes::{
+    apu::{Channel, SfxStep, SoundEffect},
+    note::Note,
+};
+
+// rising arpeggio
+pub const FOOD: SoundEffect = SoundEffect::new(
+    Channel::Pulse1,
+    &[
+        SfxStep::note(4, Note::parse("A3"), 0b10111111),
+        SfxStep::note(4, Note::parse("B3"), 0b10111111),
+        SfxStep::note(4, Note::parse("C#4"), 0b10111111),
+        SfxStep::note(4, Note::parse("D#4"), 0b10111111),
+        SfxStep::note(4, Note::parse("F4"), 0b10111111),
+        SfxStep::note(4, Note::parse("G4"), 0b10111111),
+    ],
+);
+
+// over anything else still playing
+pub const DEATH: SoundEffect = SoundEffect::new(
+    Channel::Pulse1,
+    &[
+        SfxStep::note(6, Note::parse("G#2"), 0b10111110),
+        SfxStep::note(10, Note::parse("F#2"), 0b10111000),
+        SfxStep::note(5, Note::parse("E2"), 0b10111110),
+        SfxStep::note(5, Note::parse("D2"), 0b10110110),
+    ],
+)
+.with_priority(1);